@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeySelection {
+    RoundRobin,
+    // The key with the fewest calls, so keys added later catch up with the rest
+    LeastUsed,
+}
+
+impl FromStr for KeySelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "round_robin" => Ok(KeySelection::RoundRobin),
+            "least_used" => Ok(KeySelection::LeastUsed),
+            other => Err(format!("Unsupported API key selection {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyPoolConfig {
+    pub selection: KeySelection,
+    // Time a key rejected by the upstream stays out of the rotation
+    pub quarantine_secs: u64,
+}
+
+impl KeyPoolConfig {
+    pub const DEFAULT_QUARANTINE_SECS: u64 = 60;
+}
+
+impl Default for KeyPoolConfig {
+    fn default() -> Self {
+        KeyPoolConfig {
+            selection: KeySelection::RoundRobin,
+            quarantine_secs: KeyPoolConfig::DEFAULT_QUARANTINE_SECS,
+        }
+    }
+}
+
+// Keys are only identified by their position, the key itself is never exposed
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct KeyStats {
+    pub index: usize,
+    pub calls: u64,
+    pub rejections: u64,
+    pub quarantined: bool,
+}
+
+struct PooledKey {
+    key: String,
+    calls: AtomicU64,
+    rejections: AtomicU64,
+    quarantined_until: Mutex<Option<Instant>>,
+}
+
+impl PooledKey {
+    fn is_available(&self, now: Instant) -> bool {
+        self.quarantined_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| until <= now)
+    }
+}
+
+pub struct ApiKeyPool {
+    keys: Vec<PooledKey>,
+    config: KeyPoolConfig,
+    next: AtomicUsize,
+}
+
+impl ApiKeyPool {
+    pub fn build(keys: Vec<String>, config: KeyPoolConfig) -> Self {
+        ApiKeyPool {
+            keys: keys
+                .into_iter()
+                .map(|key| PooledKey {
+                    key,
+                    calls: AtomicU64::new(0),
+                    rejections: AtomicU64::new(0),
+                    quarantined_until: Mutex::new(None),
+                })
+                .collect(),
+            config,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Picks the key of the next call, None if every key is quarantined
+    pub fn select(&self) -> Option<(usize, &str)> {
+        let now = Instant::now();
+
+        let index = match self.config.selection {
+            // Continues after the picked key, so the ones after a quarantined key
+            // don't take its share of the calls
+            KeySelection::RoundRobin => {
+                let start = self.next.load(Ordering::Relaxed);
+
+                let index = (0..self.keys.len())
+                    .map(|offset| (start + offset) % self.keys.len())
+                    .find(|&index| self.keys[index].is_available(now));
+
+                if let Some(index) = index {
+                    self.next.store(index + 1, Ordering::Relaxed);
+                }
+
+                index
+            }
+            KeySelection::LeastUsed => self
+                .keys
+                .iter()
+                .enumerate()
+                .filter(|(_, pooled)| pooled.is_available(now))
+                .min_by_key(|(_, pooled)| pooled.calls.load(Ordering::Relaxed))
+                .map(|(index, _)| index),
+        }?;
+
+        Some((index, &self.keys[index].key))
+    }
+
+    pub fn record_call(&self, index: usize) {
+        if let Some(pooled) = self.keys.get(index) {
+            pooled.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn quarantine(&self, index: usize, reason: &str) {
+        if let Some(pooled) = self.keys.get(index) {
+            pooled.rejections.fetch_add(1, Ordering::Relaxed);
+            *pooled.quarantined_until.lock().unwrap() =
+                Some(Instant::now() + Duration::from_secs(self.config.quarantine_secs));
+
+            log::warn!(
+                "API key #{} quarantined for {}s - {}",
+                index,
+                self.config.quarantine_secs,
+                reason
+            );
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|pooled| pooled.key.as_str())
+    }
+
+    pub fn stats(&self) -> Vec<KeyStats> {
+        let now = Instant::now();
+
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(index, pooled)| KeyStats {
+                index,
+                calls: pooled.calls.load(Ordering::Relaxed),
+                rejections: pooled.rejections.load(Ordering::Relaxed),
+                quarantined: !pooled.is_available(now),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_api_keys {
+    use super::*;
+
+    fn build_pool(selection: KeySelection, quarantine_secs: u64) -> ApiKeyPool {
+        ApiKeyPool::build(
+            vec!["key-a".into(), "key-b".into(), "key-c".into()],
+            KeyPoolConfig {
+                selection,
+                quarantine_secs,
+            },
+        )
+    }
+
+    fn select_keys(pool: &ApiKeyPool, calls: usize) -> Vec<String> {
+        (0..calls)
+            .map(|_| {
+                let (index, key) = pool.select().unwrap();
+                pool.record_call(index);
+                key.to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_round_robin() {
+        let pool = build_pool(KeySelection::RoundRobin, 60);
+
+        assert_eq!(
+            select_keys(&pool, 4),
+            vec!["key-a", "key-b", "key-c", "key-a"]
+        );
+
+        pool.quarantine(1, "Invalid API key");
+
+        assert_eq!(
+            select_keys(&pool, 4),
+            vec!["key-c", "key-a", "key-c", "key-a"]
+        );
+
+        let stats = pool.stats();
+
+        assert_eq!(
+            stats[1],
+            KeyStats {
+                index: 1,
+                calls: 1,
+                rejections: 1,
+                quarantined: true,
+            }
+        );
+        assert_eq!(stats[0].calls, 4);
+        assert_eq!(stats[2].calls, 3);
+    }
+
+    #[test]
+    fn check_least_used() {
+        let pool = build_pool(KeySelection::LeastUsed, 0);
+
+        select_keys(&pool, 2);
+
+        assert_eq!(pool.select().unwrap(), (2, "key-c"));
+        pool.record_call(2);
+
+        // Quarantines that already ended don't take the key out of the rotation
+        pool.quarantine(0, "Too many requests");
+
+        assert_eq!(pool.select().unwrap(), (0, "key-a"));
+        assert!(!pool.stats()[0].quarantined);
+    }
+
+    #[test]
+    fn check_quarantined_pool() {
+        let pool = build_pool(KeySelection::RoundRobin, 60);
+
+        for index in 0..3 {
+            pool.quarantine(index, "Invalid API key");
+        }
+
+        assert_eq!(pool.select(), None);
+        assert!("random".parse::<KeySelection>().is_err());
+        assert_eq!(
+            "least_used".parse::<KeySelection>(),
+            Ok(KeySelection::LeastUsed)
+        );
+    }
+}
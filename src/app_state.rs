@@ -1,107 +1,287 @@
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::future::{BoxFuture, FutureExt, Shared};
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+
+use crate::cache::{CacheBackend, CacheConfig, CacheTtl, CachedElement, ResponseCache, TtlConfig};
+use crate::disk_cache::DiskBackend;
+use crate::error::ApiError;
+use crate::models::{
+    api::APIResponse,
+    request::{LocationQuery, RequestType},
+    state::*,
+};
+use crate::providers::WeatherProvider;
+use crate::redis_cache::RedisBackend;
+use crate::resilience::CircuitState;
+
+// Upstream fetch that every concurrent request for the same key awaits
+pub type InFlightFetch = Shared<BoxFuture<'static, Result<CachedResponse, ApiError>>>;
 
-use crate::models::{api::APIResponse, state::*};
-use crate::weather_api::APIClient;
-
-pub struct CachedElement<T> {
-    pub element: T,
-    // Epoch in which the element expires
-    pub expires_at: u128,
+pub struct AppState {
+    pub provider: Box<dyn WeatherProvider>,
+    pub city_db: HashMap<(String, String), CityEntry>,
+    city_ids: HashMap<u32, CityEntry>,
+    api_cache: Box<dyn CacheBackend>,
+    cache_ttls: TtlConfig,
+    in_flight: DashMap<CacheKey, InFlightFetch>,
 }
 
-impl<T> CachedElement<T> {
-    pub fn new(element: T, object_expiry_milis: u128) -> Self {
-        Self {
-            element,
-            expires_at: Self::generate_expiry_time(object_expiry_milis),
-        }
+impl AppState {
+    // State served by OpenWeatherMap, as most tests use
+    #[cfg(test)]
+    pub fn build(
+        api_keys: Vec<String>,
+        api_config: crate::weather_api::APIConfig,
+        cache_config: CacheConfig,
+        city_list: Vec<City>,
+    ) -> Self {
+        AppState::with_provider(
+            Box::new(crate::weather_api::APIClient::build(api_keys, api_config)),
+            cache_config,
+            city_list,
+        )
     }
 
-    pub fn has_expired(&self) -> bool {
-        let current_epoch = CachedElement::<T>::generate_expiry_time(0);
-        current_epoch >= self.expires_at
+    pub fn with_provider(
+        provider: Box<dyn WeatherProvider>,
+        cache_config: CacheConfig,
+        city_list: Vec<City>,
+    ) -> Self {
+        let city_db = AppState::init_hash_table(city_list);
+        let city_ids = city_db
+            .values()
+            .map(|entry| (entry.city_id, *entry))
+            .collect();
+
+        AppState {
+            api_cache: AppState::build_cache_backend(&cache_config),
+            cache_ttls: cache_config.ttls,
+            in_flight: DashMap::new(),
+            provider,
+            city_db,
+            city_ids,
+        }
     }
 
-    fn generate_expiry_time(expiry_milis: u128) -> u128 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
-            + expiry_milis
+    // A shared cache takes precedence over a disk one, a backend that can't be
+    // set up is logged and the server runs with the memory cache alone
+    fn build_cache_backend(cache_config: &CacheConfig) -> Box<dyn CacheBackend> {
+        if let Some(redis_address) = &cache_config.redis_address {
+            match RedisBackend::build(redis_address) {
+                Ok(backend) => return Box::new(backend),
+                Err(err) => log::error!("Redis cache could not be set up - {}", err),
+            }
+        } else if let Some(disk_path) = &cache_config.disk_path {
+            match DiskBackend::open(cache_config, disk_path) {
+                Ok(backend) => return Box::new(backend),
+                Err(err) => log::error!("Disk cache could not be opened - {}", err),
+            }
+        }
+
+        // Sharded LRU, each access only locks the shard holding the key
+        Box::new(ResponseCache::build(cache_config))
     }
-}
 
-pub struct AppState {
-    pub api_client: APIClient,
-    pub city_db: HashMap<(String, String), CityEntry>,
-    api_cache: HashMap<CacheKey, CachedElement<APIResponse>>,
-}
+    pub async fn cache_response(
+        &self,
+        cache_key: CacheKey,
+        response: APIResponse,
+    ) -> Result<CachedResponse, String> {
+        if response.current.is_some()
+            || response.hourly.is_some()
+            || response.daily.is_some()
+            || response.minutely.is_some()
+            || response.alerts.is_some()
+            || response.air_quality.is_some()
+        {
+            let cache = match self.cache_ttl_for(&cache_key) {
+                Some(ttl) => CachedElement::new(response, ttl),
+                None => CachedElement::new_permanent(response),
+            };
+
+            log::debug!(
+                "Generating cache for api response - {}",
+                &cache_key.location
+            );
 
-impl AppState {
-    pub const CACHE_EXPIRY_MILIS: u128 = 600_000; // 10 minutes
+            let cached = cache.to_response();
 
-    pub fn build(api_key: String, city_list: Vec<City>) -> Self {
-        AppState {
-            api_cache: HashMap::new(),
-            api_client: crate::weather_api::APIClient::build(api_key),
-            city_db: AppState::init_hash_table(city_list),
+            self.api_cache
+                .insert(cache_key, cache)
+                .await
+                .map(|_| cached)
+        } else {
+            Err("APIResponse doesn't contain valid data!".into())
         }
     }
 
-    pub fn cache_response(
-        &mut self,
+    // Concurrent cache misses for the same key share a single upstream call,
+    // the first one starts the fetch and the rest wait on its result
+    pub fn fetch_response(
+        self: Arc<Self>,
         cache_key: CacheKey,
-        response: APIResponse,
-    ) -> Result<(), String> {
-        if response.current.is_some() || response.hourly.is_some() {
-            if !self.check_and_clear_cache(&cache_key) {
-                log::debug!("Generating cache for api response - {}", &cache_key.city_id);
+        location: ResolvedLocation,
+    ) -> InFlightFetch {
+        match self.in_flight.entry(cache_key) {
+            Entry::Occupied(entry) => {
+                log::debug!(
+                    "Waiting on in-flight api request for location - {}",
+                    &cache_key.location
+                );
+
+                entry.get().clone()
+            }
+            Entry::Vacant(entry) => {
+                let state = self.clone();
 
-                let cache = CachedElement::new(response, AppState::CACHE_EXPIRY_MILIS);
+                let fetch = async move {
+                    // The previous fetch may have finished since the caller missed the cache
+                    let result = match state.api_cache.get_fresh(&cache_key).await {
+                        Some(cached_response) => Ok(cached_response),
+                        None => state.perform_fetch(cache_key, location).await,
+                    };
 
-                let _ = self.api_cache.insert(cache_key, cache);
+                    // Removed only once the response is cached, so later misses find it there
+                    state.in_flight.remove(&cache_key);
+
+                    result
+                }
+                .boxed()
+                .shared();
 
-                return Ok(());
+                entry.insert(fetch.clone());
+                fetch
             }
+        }
+    }
 
+    // Refreshes a stale response in the background, if that fails
+    // the stale copy keeps being served until its hard TTL
+    pub fn revalidate(self: Arc<Self>, cache_key: CacheKey, location: ResolvedLocation) {
+        let fetch = self.fetch_response(cache_key, location);
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = fetch.await {
+                log::warn!(
+                    "Failed to refresh stale cache for location {} - {}",
+                    cache_key.location,
+                    err
+                );
+            }
+        });
+    }
+
+    async fn perform_fetch(
+        &self,
+        cache_key: CacheKey,
+        location: ResolvedLocation,
+    ) -> Result<CachedResponse, ApiError> {
+        if !self.provider.supports(cache_key.req_type) {
+            return Err(ApiError::Unsupported(format!(
+                "{} doesn't provide {} data",
+                self.provider.name(),
+                cache_key.req_type.name()
+            )));
+        }
+
+        let api_result = self
+            .provider
+            .fetch(cache_key.req_type, location.lat, location.lon)
+            .await;
+
+        let mut report = api_result.map_err(|err| {
             log::warn!(
-                "Tried to cache already cached api response for id - {}",
-                &cache_key.city_id
+                "Upstream request for ({}|{:?}) failed - {}",
+                cache_key.location,
+                cache_key.req_type,
+                err
             );
 
-            Err("APIResponse is already cached!".into())
-        } else {
-            Err("APIResponse doesn't contain valid data!".into())
+            err
+        })?;
+
+        // A failover chain already names the provider that served the request
+        if report.provider.is_none() {
+            report.provider = Some(self.provider.name().to_owned());
+        }
+
+        let response = APIResponse::from(report);
+
+        match self.cache_response(cache_key, response.clone()).await {
+            Ok(cached) => Ok(cached),
+            Err(msg) => {
+                log::warn!(
+                    "Failed to create cache for ({}|{:?}) - {}",
+                    cache_key.location,
+                    cache_key.req_type,
+                    msg
+                );
+
+                Ok(CachedResponse::uncached(response))
+            }
         }
     }
 
-    pub fn get_cache_for(&mut self, cache_key: &CacheKey) -> Option<&APIResponse> {
-        if self.check_and_clear_cache(cache_key) {
-            return Some(&self.api_cache.get(cache_key).unwrap().element);
+    // A None expiry means the response never changes and can be cached forever
+    pub fn cache_ttl_for(&self, cache_key: &CacheKey) -> Option<CacheTtl> {
+        match cache_key.req_type {
+            RequestType::Historical(day) if day < crate::utils::current_epoch_day() => None,
+            req_type => Some(self.cache_ttls.ttl_for(cache_key.location, req_type)),
         }
+    }
 
-        None
+    pub async fn get_cache_for(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        self.api_cache.get(cache_key).await
     }
 
-    pub fn has_valid_cache_for(&self, cache_key: &CacheKey) -> bool {
-        match self.api_cache.get(cache_key) {
-            Some(cache) => !cache.has_expired(),
-            None => false,
-        }
+    #[cfg(test)]
+    pub async fn has_valid_cache_for(&self, cache_key: &CacheKey) -> bool {
+        self.api_cache.peek(cache_key).await.is_some()
     }
 
-    fn check_and_clear_cache(&mut self, cache_key: &CacheKey) -> bool {
-        match self.api_cache.get(cache_key) {
-            Some(cache) => {
-                if cache.has_expired() {
-                    self.api_cache.remove(&cache_key);
-                    return false;
-                }
+    // Caches a response that is already stale, as if its soft TTL had passed
+    #[cfg(test)]
+    pub async fn cache_stale_response(&self, cache_key: CacheKey, response: APIResponse) {
+        let ttl = CacheTtl::from_secs(0, TtlConfig::DEFAULT_STALE_TTL_SECS);
 
-                true
-            }
-            None => false,
+        self.api_cache
+            .insert(cache_key, CachedElement::new(response, ttl))
+            .await
+            .unwrap();
+    }
+
+    pub async fn sweep_cache(&self) -> usize {
+        self.api_cache.sweep().await
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            cache: self.api_cache.stats(),
+            quota: self.provider.quota_stats(),
+            api_keys: self.provider.key_stats(),
+            providers: self.provider.provider_stats(),
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        let upstream = self.provider.circuit_status();
+        let fallbacks = self.provider.fallbacks();
+
+        // A failing fallback leaves the server with nowhere to go once the primary fails too
+        let all_closed = std::iter::once(&upstream)
+            .chain(fallbacks.iter().map(|fallback| &fallback.upstream))
+            .all(|circuit| circuit.state == CircuitState::Closed);
+
+        Health {
+            status: if all_closed {
+                HealthStatus::Ok
+            } else {
+                HealthStatus::Degraded
+            },
+            provider: self.provider.name(),
+            upstream,
+            fallbacks,
         }
     }
 
@@ -127,30 +307,23 @@ impl AppState {
             None
         }
     }
-}
-
-#[cfg(test)]
-mod test_cached_element {
-    use super::*;
 
-    use std::thread::sleep;
-    use std::time::Duration;
-
-    #[test]
-    fn check_no_cache_config() {
-        let cached_obj = CachedElement::new(10, 0);
-
-        assert!(cached_obj.has_expired());
+    pub fn get_city_keys_for_id(&self, city_id: u32) -> Option<CityEntry> {
+        self.city_ids.get(&city_id).copied()
     }
 
-    #[test]
-    fn check_cache_persistence() {
-        let cached_obj = CachedElement::new(10, 1000);
-        assert!(!cached_obj.has_expired());
-
-        sleep(Duration::from_millis(1100));
-
-        assert!(cached_obj.has_expired());
+    pub fn resolve_location(&self, location: &LocationQuery) -> Option<ResolvedLocation> {
+        match location {
+            LocationQuery::Coordinates { lat, lon } => {
+                ResolvedLocation::from_coordinates(*lat, *lon)
+            }
+            LocationQuery::CityId { city_id } => self
+                .get_city_keys_for_id(*city_id)
+                .map(ResolvedLocation::from_city),
+            LocationQuery::CityQuery { city_query } => self
+                .get_city_keys_for_query(city_query)
+                .map(ResolvedLocation::from_city),
+        }
     }
 }
 
@@ -158,59 +331,243 @@ mod test_cached_element {
 mod test_app_state {
     use super::*;
 
-    use crate::models::{api::WeatherCurrent, request::TemperatureFormat};
+    use crate::models::api::WeatherCurrent;
+    use crate::weather_api::APIConfig;
 
-    #[test]
-    fn check_cache_storage() {
-        let mut app_state = AppState::build("11".into(), vec![]);
-
-        let cache_key = CacheKey::from(
-            1,
-            TemperatureFormat::Metric,
-            crate::RequestType::CurrentWeather,
+    #[actix_rt::test]
+    async fn check_cache_storage() {
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            CacheConfig::default(),
+            vec![],
         );
 
-        let api_response = APIResponse {
-            lat: None,
-            lon: None,
-            cod: None,
-            message: None,
-            current: None,
-            hourly: None,
-        };
+        let cache_key = CacheKey::from(CacheLocation::City(1), RequestType::CurrentWeather);
 
-        assert!(!app_state.has_valid_cache_for(&cache_key));
+        let api_response = APIResponse::default();
 
-        assert!(app_state.cache_response(cache_key, api_response).is_err());
+        assert!(!app_state.has_valid_cache_for(&cache_key).await);
 
-        assert!(!app_state.has_valid_cache_for(&cache_key));
+        assert!(app_state
+            .cache_response(cache_key, api_response)
+            .await
+            .is_err());
+
+        assert!(!app_state.has_valid_cache_for(&cache_key).await);
 
         let api_response = APIResponse {
-            lat: None,
-            lon: None,
-            cod: None,
-            message: None,
             current: Some(WeatherCurrent {
                 dt: 1,
-                sunrise: 1,
-                sunset: 1,
+                sunrise: Some(1),
+                sunset: Some(1),
                 temp: 0.0,
-                feels_like: 0.0,
-                pressure: 1,
+                feels_like: Some(0.0),
+                pressure: 1.0,
                 humidity: 1,
-                dew_point: 0.0,
-                uvi: 0.0,
+                dew_point: Some(0.0),
+                uvi: Some(0.0),
                 clouds: 1,
-                visibility: 1,
+                visibility: Some(1.0),
                 wind_speed: 0.0,
                 wind_deg: 1,
-                conditions: None,
+                ..Default::default()
             }),
-            hourly: None,
+            ..Default::default()
+        };
+
+        assert!(app_state
+            .cache_response(cache_key, api_response.clone())
+            .await
+            .is_ok());
+
+        assert!(app_state.has_valid_cache_for(&cache_key).await);
+        assert!(app_state.get_cache_for(&cache_key).await.is_some());
+
+        assert!(app_state
+            .cache_response(cache_key, api_response)
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn check_cache_backend_fallback() {
+        let cache_config = CacheConfig {
+            disk_path: Some("/nonexistent/weather-retrieve/cache.jsonl".into()),
+            ..Default::default()
+        };
+
+        // The server still caches responses in memory
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            cache_config,
+            vec![],
+        );
+
+        let cache_key = CacheKey::from(CacheLocation::City(1), RequestType::CurrentWeather);
+        let api_response: APIResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/onecall.json")).unwrap();
+
+        assert!(app_state
+            .cache_response(cache_key, api_response)
+            .await
+            .is_ok());
+        assert!(app_state.has_valid_cache_for(&cache_key).await);
+    }
+
+    #[actix_rt::test]
+    async fn check_disk_cache_reload() {
+        let mut disk_path = std::env::temp_dir();
+        disk_path.push(format!(
+            "weather-retrieve-state-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&disk_path);
+
+        let cache_config = CacheConfig {
+            disk_path: Some(disk_path.clone()),
+            ..Default::default()
+        };
+
+        let cache_key = CacheKey::from(CacheLocation::City(1), RequestType::CurrentWeather);
+        let api_response: APIResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/onecall.json")).unwrap();
+
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            cache_config.clone(),
+            vec![],
+        );
+        assert!(app_state
+            .cache_response(cache_key, api_response)
+            .await
+            .is_ok());
+        drop(app_state);
+
+        // A restarted server serves the response without querying the upstream
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            cache_config,
+            vec![],
+        );
+        let cached = app_state.get_cache_for(&cache_key).await.unwrap();
+
+        assert!(!cached.stale);
+        assert!(cached.response.current.is_some());
+
+        std::fs::remove_file(&disk_path).unwrap();
+    }
+
+    #[test]
+    fn check_cache_expiry() {
+        let today = crate::utils::current_epoch_day();
+
+        let cache_config = CacheConfig {
+            ttls: TtlConfig::from_json(
+                r#"{ "cities": { "1": { "nowcast": { "ttl_secs": 30 } } } }"#,
+            )
+            .unwrap(),
+            ..Default::default()
         };
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            cache_config,
+            vec![],
+        );
 
-        assert!(app_state.cache_response(cache_key, api_response).is_ok());
+        let cache_key = |location, req_type| CacheKey::from(location, req_type);
 
-        assert!(app_state.has_valid_cache_for(&cache_key));
+        let default_ttl = CacheTtl::from_secs(
+            TtlConfig::DEFAULT_TTL_SECS,
+            TtlConfig::DEFAULT_STALE_TTL_SECS,
+        );
+
+        assert_eq!(
+            app_state.cache_ttl_for(&cache_key(
+                CacheLocation::City(1),
+                RequestType::CurrentWeather
+            )),
+            Some(default_ttl)
+        );
+        assert_eq!(
+            app_state.cache_ttl_for(&cache_key(CacheLocation::City(2), RequestType::Nowcast)),
+            Some(CacheTtl::from_secs(
+                TtlConfig::NOWCAST_TTL_SECS,
+                TtlConfig::NOWCAST_STALE_TTL_SECS
+            ))
+        );
+        assert_eq!(
+            app_state.cache_ttl_for(&cache_key(CacheLocation::City(1), RequestType::Nowcast)),
+            Some(CacheTtl::from_secs(30, TtlConfig::NOWCAST_STALE_TTL_SECS))
+        );
+        assert_eq!(
+            app_state.cache_ttl_for(&cache_key(
+                CacheLocation::City(1),
+                RequestType::Historical(today)
+            )),
+            Some(default_ttl)
+        );
+        assert_eq!(
+            app_state.cache_ttl_for(&cache_key(
+                CacheLocation::City(1),
+                RequestType::Historical(today - 1)
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn check_location_resolution() {
+        let city = City {
+            id: 3117735,
+            lat: 40.4165,
+            lon: -3.7026,
+            name: "Madrid".into(),
+            country: "ES".into(),
+        };
+
+        let app_state = AppState::build(
+            vec!["11".into()],
+            APIConfig::default(),
+            CacheConfig::default(),
+            vec![city],
+        );
+
+        let by_query = app_state
+            .resolve_location(&LocationQuery::CityQuery {
+                city_query: "Madrid,ES".into(),
+            })
+            .unwrap();
+        let by_id = app_state
+            .resolve_location(&LocationQuery::CityId { city_id: 3117735 })
+            .unwrap();
+
+        assert_eq!(by_query.cache_location, CacheLocation::City(3117735));
+        assert_eq!(by_id.cache_location, CacheLocation::City(3117735));
+
+        let by_coords = app_state
+            .resolve_location(&LocationQuery::Coordinates {
+                lat: 40.4165,
+                lon: -3.7026,
+            })
+            .unwrap();
+
+        assert_eq!(
+            by_coords.cache_location,
+            CacheLocation::Coordinates(4042, -370)
+        );
+
+        assert!(app_state
+            .resolve_location(&LocationQuery::CityId { city_id: 1 })
+            .is_none());
+        assert!(app_state
+            .resolve_location(&LocationQuery::CityQuery {
+                city_query: "Madrid".into(),
+            })
+            .is_none());
     }
 }
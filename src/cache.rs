@@ -0,0 +1,824 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::models::{
+    api::APIResponse,
+    request::RequestType,
+    state::{CacheKey, CacheLocation, CacheStats, CachedResponse},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CacheTtl {
+    // Until then the element is fresh, afterwards it is served as stale while refreshed
+    pub soft_milis: u128,
+    // After it the element can no longer be served
+    pub hard_milis: u128,
+}
+
+impl CacheTtl {
+    pub fn from(soft_milis: u128, hard_milis: u128) -> Self {
+        CacheTtl {
+            soft_milis,
+            hard_milis: hard_milis.max(soft_milis),
+        }
+    }
+
+    pub fn from_secs(ttl_secs: u64, stale_ttl_secs: u64) -> Self {
+        CacheTtl::from(ttl_secs as u128 * 1000, stale_ttl_secs as u128 * 1000)
+    }
+}
+
+// TTLs for each request type, which can be overridden for specific cities
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TtlConfig {
+    request_types: HashMap<&'static str, CacheTtl>,
+    cities: HashMap<u32, HashMap<&'static str, CacheTtl>>,
+}
+
+#[derive(Deserialize)]
+struct TtlEntry {
+    ttl_secs: u64,
+    stale_ttl_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TtlConfigFile {
+    #[serde(default)]
+    cities: HashMap<u32, HashMap<String, TtlEntry>>,
+    #[serde(flatten)]
+    request_types: HashMap<String, TtlEntry>,
+}
+
+impl TtlConfig {
+    pub const DEFAULT_TTL_SECS: u64 = 600; // 10 minutes
+    pub const NOWCAST_TTL_SECS: u64 = 60; // 1 minute
+
+    // How long responses can still be served as stale when they can't be refreshed
+    pub const DEFAULT_STALE_TTL_SECS: u64 = 3_600; // 1 hour
+    pub const NOWCAST_STALE_TTL_SECS: u64 = 600; // 10 minutes
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let config_file: TtlConfigFile =
+            serde_json::from_str(json).map_err(|err| err.to_string())?;
+
+        let request_types = TtlConfig::resolve_entries(config_file.request_types)?;
+        let cities = config_file
+            .cities
+            .into_iter()
+            .map(|(city_id, entries)| Ok((city_id, TtlConfig::resolve_entries(entries)?)))
+            .collect::<Result<_, String>>()?;
+
+        Ok(TtlConfig {
+            request_types,
+            cities,
+        })
+    }
+
+    fn resolve_entries(
+        entries: HashMap<String, TtlEntry>,
+    ) -> Result<HashMap<&'static str, CacheTtl>, String> {
+        entries
+            .into_iter()
+            .map(|(name, entry)| {
+                let name = RequestType::VARIANTS
+                    .iter()
+                    .map(RequestType::name)
+                    .find(|known_name| *known_name == name)
+                    .ok_or_else(|| format!("Unknown request type {}", name))?;
+
+                let stale_ttl_secs = entry
+                    .stale_ttl_secs
+                    .unwrap_or_else(|| TtlConfig::builtin_stale_ttl_secs(name));
+
+                Ok((name, CacheTtl::from_secs(entry.ttl_secs, stale_ttl_secs)))
+            })
+            .collect()
+    }
+
+    // City overrides only apply to locations queried by city
+    pub fn ttl_for(&self, location: CacheLocation, req_type: RequestType) -> CacheTtl {
+        let name = req_type.name();
+
+        let city_ttl = match location {
+            CacheLocation::City(city_id) => self
+                .cities
+                .get(&city_id)
+                .and_then(|city_ttls| city_ttls.get(name)),
+            CacheLocation::Coordinates(_, _) => None,
+        };
+
+        city_ttl
+            .or_else(|| self.request_types.get(name))
+            .copied()
+            .unwrap_or_else(|| {
+                CacheTtl::from_secs(
+                    TtlConfig::builtin_ttl_secs(name),
+                    TtlConfig::builtin_stale_ttl_secs(name),
+                )
+            })
+    }
+
+    fn builtin_ttl_secs(name: &str) -> u64 {
+        if name == RequestType::Nowcast.name() {
+            TtlConfig::NOWCAST_TTL_SECS
+        } else {
+            TtlConfig::DEFAULT_TTL_SECS
+        }
+    }
+
+    fn builtin_stale_ttl_secs(name: &str) -> u64 {
+        if name == RequestType::Nowcast.name() {
+            TtlConfig::NOWCAST_STALE_TTL_SECS
+        } else {
+            TtlConfig::DEFAULT_STALE_TTL_SECS
+        }
+    }
+}
+
+pub type CacheEntry = (CacheKey, CachedElement<APIResponse>);
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CachedElement<T> {
+    pub element: T,
+    // Epoch in which the element was cached
+    pub cached_at: u128,
+    // Epoch in which the element becomes stale
+    pub stale_at: u128,
+    // Epoch in which the element expires
+    pub expires_at: u128,
+}
+
+impl<T> CachedElement<T> {
+    pub fn new(element: T, ttl: CacheTtl) -> Self {
+        Self {
+            element,
+            cached_at: Self::generate_expiry_time(0),
+            stale_at: Self::generate_expiry_time(ttl.soft_milis),
+            expires_at: Self::generate_expiry_time(ttl.hard_milis),
+        }
+    }
+
+    pub fn new_permanent(element: T) -> Self {
+        Self {
+            element,
+            cached_at: Self::generate_expiry_time(0),
+            stale_at: u128::MAX,
+            expires_at: u128::MAX,
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        let current_epoch = CachedElement::<T>::generate_expiry_time(0);
+        current_epoch >= self.stale_at
+    }
+
+    pub fn has_expired(&self) -> bool {
+        let current_epoch = CachedElement::<T>::generate_expiry_time(0);
+        current_epoch >= self.expires_at
+    }
+
+    // None for elements that never expire
+    pub fn milis_to_expiry(&self) -> Option<u128> {
+        CachedElement::<T>::milis_until(self.expires_at)
+    }
+
+    // None for elements that never go stale
+    pub fn milis_to_stale(&self) -> Option<u128> {
+        CachedElement::<T>::milis_until(self.stale_at)
+    }
+
+    fn milis_until(time: u128) -> Option<u128> {
+        match time {
+            u128::MAX => None,
+            time => Some(time.saturating_sub(CachedElement::<T>::generate_expiry_time(0))),
+        }
+    }
+
+    fn generate_expiry_time(expiry_milis: u128) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            + expiry_milis
+    }
+}
+
+impl CachedElement<APIResponse> {
+    pub fn to_response(&self) -> CachedResponse {
+        CachedResponse {
+            response: self.element.clone(),
+            stale: self.is_stale(),
+            cached_at: Some((self.cached_at / 1000) as u64),
+            expires_at: match self.stale_at {
+                u128::MAX => None,
+                stale_at => Some((stale_at / 1000) as u64),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    // Approximate, measured as the size of the serialized responses
+    pub max_bytes: usize,
+    pub shards: usize,
+    pub sweep_interval_secs: u64,
+    pub ttls: TtlConfig,
+    // When set, cached responses are also kept on disk and reloaded on start
+    pub disk_path: Option<PathBuf>,
+    // When set, responses are cached in a Redis server shared by every replica
+    pub redis_address: Option<String>,
+}
+
+impl CacheConfig {
+    pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+    pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024; // 64 MiB
+    pub const DEFAULT_SHARDS: usize = 16;
+    pub const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_entries: CacheConfig::DEFAULT_MAX_ENTRIES,
+            max_bytes: CacheConfig::DEFAULT_MAX_BYTES,
+            shards: CacheConfig::DEFAULT_SHARDS,
+            sweep_interval_secs: CacheConfig::DEFAULT_SWEEP_INTERVAL_SECS,
+            ttls: TtlConfig::default(),
+            disk_path: None,
+            redis_address: None,
+        }
+    }
+}
+
+struct SizedElement {
+    cache: CachedElement<APIResponse>,
+    bytes: usize,
+}
+
+struct CacheShard {
+    entries: LruCache<CacheKey, SizedElement>,
+    bytes: usize,
+}
+
+impl CacheShard {
+    fn remove(&mut self, cache_key: &CacheKey) -> Option<SizedElement> {
+        let removed = self.entries.pop(cache_key);
+
+        if let Some(element) = &removed {
+            self.bytes -= element.bytes;
+        }
+
+        removed
+    }
+}
+
+// Storage for the cached api responses, lookups that fail, like a shared cache
+// that can't be reached, are reported as misses so the upstream is queried instead
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, cache_key: &CacheKey) -> Option<CachedResponse>;
+
+    // Unlike get, the lookup is not counted, expired responses are still left out
+    async fn peek(&self, cache_key: &CacheKey) -> Option<CachedResponse>;
+
+    // Only returns responses that are not stale, and the lookup is not counted
+    async fn get_fresh(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        self.peek(cache_key).await.filter(|cached| !cached.stale)
+    }
+
+    // Fails if the key already holds a response that is not stale
+    async fn insert(
+        &self,
+        cache_key: CacheKey,
+        cache: CachedElement<APIResponse>,
+    ) -> Result<(), String>;
+
+    // Backends whose entries expire on their own have nothing to sweep
+    async fn sweep(&self) -> usize {
+        0
+    }
+
+    fn stats(&self) -> CacheStats;
+}
+
+#[derive(Default)]
+pub struct CacheCounters {
+    hits: AtomicU64,
+    stale_hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl CacheCounters {
+    pub fn record_lookup(&self, cached_response: Option<&CachedResponse>) {
+        match cached_response {
+            Some(cached) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                if cached.stale {
+                    self.stale_hits.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn stats(&self, entries: Option<usize>, bytes: Option<usize>) -> CacheStats {
+        CacheStats {
+            entries,
+            bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// The limits are split evenly between the shards, and each shard evicts
+// its least recently used entries once it goes over its share
+pub struct ResponseCache {
+    shards: Vec<Mutex<CacheShard>>,
+    max_shard_entries: usize,
+    max_shard_bytes: usize,
+    counters: CacheCounters,
+}
+
+impl ResponseCache {
+    pub fn build(config: &CacheConfig) -> Self {
+        let shard_count = config.shards.max(1);
+
+        let shards = (0..shard_count)
+            .map(|_| {
+                Mutex::new(CacheShard {
+                    entries: LruCache::unbounded(),
+                    bytes: 0,
+                })
+            })
+            .collect();
+
+        ResponseCache {
+            shards,
+            max_shard_entries: config.max_entries.div_ceil(shard_count),
+            max_shard_bytes: config.max_bytes.div_ceil(shard_count),
+            counters: CacheCounters::default(),
+        }
+    }
+
+    fn shard_for(&self, cache_key: &CacheKey) -> &Mutex<CacheShard> {
+        let mut hasher = DefaultHasher::new();
+        cache_key.hash(&mut hasher);
+
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    // Responses are cloned out of the cache so no shard lock outlives the call
+    pub fn get(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        let mut shard = self.shard_for(cache_key).lock().unwrap();
+
+        let cached_response = match shard.entries.get(cache_key) {
+            Some(element) if !element.cache.has_expired() => Some(element.cache.to_response()),
+            Some(_) => {
+                shard.remove(cache_key);
+                self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => None,
+        };
+
+        self.counters.record_lookup(cached_response.as_ref());
+
+        cached_response
+    }
+
+    // Unlike get, it neither refreshes the entry nor counts as a hit or a miss
+    pub fn peek(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        let shard = self.shard_for(cache_key).lock().unwrap();
+
+        match shard.entries.peek(cache_key) {
+            Some(element) if !element.cache.has_expired() => Some(element.cache.to_response()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(
+        &self,
+        cache_key: CacheKey,
+        cache: CachedElement<APIResponse>,
+    ) -> Result<(), String> {
+        // Measured before locking, serializing a forecast is not free
+        let bytes = serde_json::to_vec(&cache.element)
+            .map(|serialized| serialized.len())
+            .map_err(|err| err.to_string())?;
+
+        if bytes > self.max_shard_bytes {
+            return Err(format!(
+                "APIResponse is too large to cache ({} bytes)",
+                bytes
+            ));
+        }
+
+        let mut shard = self.shard_for(&cache_key).lock().unwrap();
+
+        // Stale entries are replaced by the refreshed response
+        match shard.entries.peek(&cache_key) {
+            Some(element) if !element.cache.is_stale() => {
+                return Err("APIResponse is already cached!".into());
+            }
+            Some(element) => {
+                if element.cache.has_expired() {
+                    self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+                }
+
+                shard.remove(&cache_key);
+            }
+            None => {}
+        }
+
+        shard.entries.put(cache_key, SizedElement { cache, bytes });
+        shard.bytes += bytes;
+
+        while shard.entries.len() > self.max_shard_entries || shard.bytes > self.max_shard_bytes {
+            match shard.entries.pop_lru() {
+                Some((evicted_key, element)) => {
+                    log::debug!("Evicting cached api response - {}", evicted_key.location);
+
+                    shard.bytes -= element.bytes;
+                    self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drops every expired entry, returning how many were removed
+    pub fn sweep(&self) -> usize {
+        let mut swept = 0;
+
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+
+            let expired_keys = shard
+                .entries
+                .iter()
+                .filter(|(_, element)| element.cache.has_expired())
+                .map(|(cache_key, _)| *cache_key)
+                .collect::<Vec<CacheKey>>();
+
+            for cache_key in &expired_keys {
+                shard.remove(cache_key);
+            }
+
+            swept += expired_keys.len();
+        }
+
+        self.counters
+            .expirations
+            .fetch_add(swept as u64, Ordering::Relaxed);
+
+        swept
+    }
+
+    // Clones every entry that has not expired, least recently used first
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let mut entries = vec![];
+
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+
+            entries.extend(
+                shard
+                    .entries
+                    .iter()
+                    .rev()
+                    .filter(|(_, element)| !element.cache.has_expired())
+                    .map(|(cache_key, element)| (*cache_key, element.cache.clone())),
+            );
+        }
+
+        entries
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = self.shards.iter().fold((0, 0), |(entries, bytes), shard| {
+            let shard = shard.lock().unwrap();
+            (entries + shard.entries.len(), bytes + shard.bytes)
+        });
+
+        self.counters.stats(Some(entries), Some(bytes))
+    }
+}
+
+#[async_trait]
+impl CacheBackend for ResponseCache {
+    async fn get(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        ResponseCache::get(self, cache_key)
+    }
+
+    async fn peek(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        ResponseCache::peek(self, cache_key)
+    }
+
+    async fn insert(
+        &self,
+        cache_key: CacheKey,
+        cache: CachedElement<APIResponse>,
+    ) -> Result<(), String> {
+        ResponseCache::insert(self, cache_key, cache)
+    }
+
+    async fn sweep(&self) -> usize {
+        ResponseCache::sweep(self)
+    }
+
+    fn stats(&self) -> CacheStats {
+        ResponseCache::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod test_cached_element {
+    use super::*;
+
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn check_no_cache_config() {
+        let cached_obj = CachedElement::new(10, CacheTtl::from(0, 0));
+
+        assert!(cached_obj.is_stale());
+        assert!(cached_obj.has_expired());
+    }
+
+    #[test]
+    fn check_permanent_cache() {
+        let cached_obj = CachedElement::new_permanent(10);
+
+        assert!(!cached_obj.is_stale());
+        assert!(!cached_obj.has_expired());
+    }
+
+    #[test]
+    fn check_cache_persistence() {
+        let cached_obj = CachedElement::new(10, CacheTtl::from(1000, 1000));
+        assert!(!cached_obj.has_expired());
+
+        sleep(Duration::from_millis(1100));
+
+        assert!(cached_obj.has_expired());
+    }
+
+    #[test]
+    fn check_stale_cache() {
+        let cached_obj = CachedElement::new(10, CacheTtl::from(0, 60_000));
+
+        assert!(cached_obj.is_stale());
+        assert!(!cached_obj.has_expired());
+
+        // The hard TTL is never shorter than the soft one
+        assert_eq!(CacheTtl::from(1000, 10), CacheTtl::from(1000, 1000));
+    }
+}
+
+#[cfg(test)]
+mod test_ttl_config {
+    use super::*;
+
+    #[test]
+    fn check_builtin_ttls() {
+        let ttls = TtlConfig::default();
+
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(1), RequestType::CurrentWeather),
+            CacheTtl::from_secs(600, 3_600)
+        );
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::Coordinates(1, 1), RequestType::Nowcast),
+            CacheTtl::from_secs(60, 600)
+        );
+    }
+
+    #[test]
+    fn check_configured_ttls() {
+        let ttls = TtlConfig::from_json(
+            r#"{
+                "weather": { "ttl_secs": 300 },
+                "forecast": { "ttl_secs": 1800, "stale_ttl_secs": 7200 },
+                "cities": {
+                    "3117735": { "weather": { "ttl_secs": 60, "stale_ttl_secs": 120 } }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(1), RequestType::CurrentWeather),
+            CacheTtl::from_secs(300, 3_600)
+        );
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(1), RequestType::WeatherForecast),
+            CacheTtl::from_secs(1800, 7200)
+        );
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(3117735), RequestType::CurrentWeather),
+            CacheTtl::from_secs(60, 120)
+        );
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(3117735), RequestType::WeatherForecast),
+            CacheTtl::from_secs(1800, 7200)
+        );
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(1), RequestType::Historical(1)),
+            CacheTtl::from_secs(600, 3_600)
+        );
+
+        let ttls = TtlConfig::from_json(r#"{ "daily": { "ttl_secs": 7200 } }"#);
+        assert!(ttls.is_err());
+
+        let ttls = TtlConfig::from_json(r#"{ "forecast_daily": { "ttl_secs": 7200 } }"#).unwrap();
+        // The stale TTL is never shorter than the fresh one
+        assert_eq!(
+            ttls.ttl_for(CacheLocation::City(1), RequestType::DailyForecast),
+            CacheTtl::from_secs(7200, 7200)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_response_cache {
+    use super::*;
+
+    use crate::models::{api::WeatherMinutely, request::RequestType, state::CacheLocation};
+
+    // A single shard makes the eviction order global and predictable
+    fn build_cache(max_entries: usize, max_bytes: usize) -> ResponseCache {
+        ResponseCache::build(&CacheConfig {
+            max_entries,
+            max_bytes,
+            shards: 1,
+            ..Default::default()
+        })
+    }
+
+    fn cache_key(city_id: u32) -> CacheKey {
+        CacheKey::from(CacheLocation::City(city_id), RequestType::Nowcast)
+    }
+
+    fn ttl(milis: u128) -> CacheTtl {
+        CacheTtl::from(milis, milis)
+    }
+
+    fn response(minutes: u32) -> APIResponse {
+        APIResponse {
+            minutely: Some(
+                (0..minutes)
+                    .map(|dt| WeatherMinutely {
+                        dt,
+                        precipitation: 0.0,
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_entry_limit_eviction() {
+        let cache = build_cache(2, usize::MAX);
+
+        for city_id in 1..=2 {
+            assert!(cache
+                .insert(
+                    cache_key(city_id),
+                    CachedElement::new(response(1), ttl(60_000))
+                )
+                .is_ok());
+        }
+
+        // Reading the first entry makes the second one the least recently used
+        assert!(cache.get(&cache_key(1)).is_some());
+
+        assert!(cache
+            .insert(cache_key(3), CachedElement::new(response(1), ttl(60_000)))
+            .is_ok());
+
+        assert!(cache.peek(&cache_key(1)).is_some());
+        assert!(cache.peek(&cache_key(2)).is_none());
+        assert!(cache.peek(&cache_key(3)).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, Some(2));
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 1);
+    }
+
+    #[test]
+    fn check_byte_limit_eviction() {
+        let entry_bytes = serde_json::to_vec(&response(10)).unwrap().len();
+        let cache = build_cache(usize::MAX, entry_bytes * 2);
+
+        for city_id in 1..=3 {
+            assert!(cache
+                .insert(
+                    cache_key(city_id),
+                    CachedElement::new(response(10), ttl(60_000))
+                )
+                .is_ok());
+        }
+
+        assert!(cache.peek(&cache_key(1)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, Some(2));
+        assert_eq!(stats.bytes, Some(entry_bytes * 2));
+        assert_eq!(stats.evictions, 1);
+
+        // Responses bigger than the share of the cache of their shard are never stored
+        assert!(cache
+            .insert(cache_key(4), CachedElement::new(response(100), ttl(60_000)))
+            .is_err());
+        assert_eq!(cache.stats().entries, Some(2));
+    }
+
+    #[test]
+    fn check_expired_entries() {
+        let cache = build_cache(10, usize::MAX);
+
+        assert!(cache.get(&cache_key(1)).is_none());
+
+        assert!(cache
+            .insert(cache_key(1), CachedElement::new(response(1), ttl(0)))
+            .is_ok());
+        assert!(cache
+            .insert(cache_key(2), CachedElement::new(response(1), ttl(0)))
+            .is_ok());
+        assert!(cache
+            .insert(cache_key(3), CachedElement::new_permanent(response(1)))
+            .is_ok());
+
+        assert!(cache.get(&cache_key(1)).is_none());
+
+        assert_eq!(cache.sweep(), 1);
+        assert!(cache.peek(&cache_key(3)).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, Some(1));
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.expirations, 2);
+        assert_eq!(stats.evictions, 0);
+    }
+
+    #[test]
+    fn check_stale_entries() {
+        let cache = build_cache(10, usize::MAX);
+
+        assert!(cache
+            .insert(
+                cache_key(1),
+                CachedElement::new(response(1), CacheTtl::from(0, 60_000))
+            )
+            .is_ok());
+
+        let cached = cache.get(&cache_key(1)).unwrap();
+        assert!(cached.stale);
+        assert!(cache.peek(&cache_key(1)).unwrap().stale);
+
+        // Stale entries are kept by the sweeper and replaced once refreshed
+        assert_eq!(cache.sweep(), 0);
+
+        assert!(cache
+            .insert(cache_key(1), CachedElement::new(response(2), ttl(60_000)))
+            .is_ok());
+
+        let cached = cache.get(&cache_key(1)).unwrap();
+        assert!(!cached.stale);
+        assert_eq!(cached.response.minutely.unwrap().len(), 2);
+
+        assert!(cache
+            .insert(cache_key(1), CachedElement::new(response(3), ttl(60_000)))
+            .is_err());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, Some(1));
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.stale_hits, 1);
+        assert_eq!(stats.expirations, 0);
+    }
+}
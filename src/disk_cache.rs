@@ -0,0 +1,453 @@
+use actix_web::{error::BlockingError, web};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::cache::{CacheBackend, CacheConfig, CacheEntry, CachedElement, ResponseCache};
+use crate::models::{
+    api::APIResponse,
+    state::{CacheKey, CacheStats, CachedResponse},
+};
+
+#[derive(Deserialize)]
+struct DiskRecord {
+    key: CacheKey,
+    cache: CachedElement<APIResponse>,
+}
+
+struct DiskFile {
+    file: File,
+    // Records in the file, including the ones superseded by later records
+    records: usize,
+}
+
+// Append-only file with one JSON record per line, every cached response is appended
+// to it and, since later records win, it is compacted once it holds too many stale ones
+pub struct DiskCache {
+    path: PathBuf,
+    disk_file: Mutex<DiskFile>,
+}
+
+impl DiskCache {
+    // Files smaller than this are never compacted at runtime
+    pub const MIN_COMPACTION_RECORDS: usize = 1_000;
+
+    // Returns the cache along with the entries that had not expired yet, unreadable
+    // records, like a line cut short by a crash, are skipped instead of failing
+    pub fn open(path: &Path) -> Result<(Self, Vec<CacheEntry>), String> {
+        let entries = if path.exists() {
+            DiskCache::read_entries(path)?
+        } else {
+            vec![]
+        };
+
+        DiskCache::write_entries(path, &entries)?;
+
+        let disk_cache = DiskCache {
+            path: path.to_path_buf(),
+            disk_file: Mutex::new(DiskFile {
+                file: DiskCache::open_append(path)?,
+                records: entries.len(),
+            }),
+        };
+
+        Ok((disk_cache, entries))
+    }
+
+    fn read_entries(path: &Path) -> Result<Vec<CacheEntry>, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+
+        let mut entries = HashMap::new();
+        let mut corrupted = 0;
+
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line.map_err(|err| err.to_string())?;
+
+            match serde_json::from_slice::<DiskRecord>(&line) {
+                Ok(record) => {
+                    entries.insert(record.key, record.cache);
+                }
+                Err(_) if line.iter().all(u8::is_ascii_whitespace) => {}
+                Err(_) => corrupted += 1,
+            }
+        }
+
+        if corrupted > 0 {
+            log::warn!(
+                "Skipped {} corrupted records from the disk cache at {}",
+                corrupted,
+                path.to_string_lossy()
+            );
+        }
+
+        let mut entries = entries
+            .into_iter()
+            .filter(|(_, cache)| !cache.has_expired())
+            .collect::<Vec<CacheEntry>>();
+
+        // Oldest first, so the most recent ones end up as the most recently used
+        entries.sort_by_key(|(_, cache)| cache.cached_at);
+
+        Ok(entries)
+    }
+
+    // Writes to a temporary file first, so a crash never leaves a half written cache
+    fn write_entries(path: &Path, entries: &[CacheEntry]) -> Result<(), String> {
+        let tmp_path = path.with_extension("tmp");
+
+        let mut content = Vec::new();
+        for (key, cache) in entries {
+            DiskCache::serialize_record(&mut content, *key, cache)?;
+        }
+
+        let mut tmp_file = File::create(&tmp_path).map_err(|err| err.to_string())?;
+        tmp_file
+            .write_all(&content)
+            .and_then(|_| tmp_file.sync_all())
+            .map_err(|err| err.to_string())?;
+
+        std::fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+    }
+
+    fn open_append(path: &Path) -> Result<File, String> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn serialize_record(
+        buffer: &mut Vec<u8>,
+        key: CacheKey,
+        cache: &CachedElement<APIResponse>,
+    ) -> Result<(), String> {
+        #[derive(Serialize)]
+        struct DiskRecordRef<'a> {
+            key: CacheKey,
+            cache: &'a CachedElement<APIResponse>,
+        }
+
+        serde_json::to_writer(&mut *buffer, &DiskRecordRef { key, cache })
+            .map_err(|err| err.to_string())?;
+        buffer.push(b'\n');
+
+        Ok(())
+    }
+
+    // Takes a record already serialized with serialize_record, so it is written with a single call
+    pub fn append(&self, record: &[u8]) -> Result<(), String> {
+        let mut disk_file = self.disk_file.lock().unwrap();
+
+        disk_file
+            .file
+            .write_all(record)
+            .map_err(|err| err.to_string())?;
+        disk_file.records += 1;
+
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self, live_entries: usize) -> bool {
+        let records = self.disk_file.lock().unwrap().records;
+
+        records >= DiskCache::MIN_COMPACTION_RECORDS && records > live_entries * 2
+    }
+
+    // The snapshot is taken while holding the file, so no record appended
+    // in the meantime is lost when the file is replaced
+    pub fn compact<F>(&self, snapshot: F) -> Result<usize, String>
+    where
+        F: FnOnce() -> Vec<CacheEntry>,
+    {
+        let mut disk_file = self.disk_file.lock().unwrap();
+
+        let entries = snapshot();
+
+        DiskCache::write_entries(&self.path, &entries)?;
+
+        disk_file.file = DiskCache::open_append(&self.path)?;
+        disk_file.records = entries.len();
+
+        Ok(entries.len())
+    }
+}
+
+// Memory cache backed by a disk cache, lookups are served from memory alone. The
+// file is only written from the blocking thread pool, never from the actix workers
+pub struct DiskBackend {
+    memory: Arc<ResponseCache>,
+    disk: Arc<DiskCache>,
+}
+
+impl DiskBackend {
+    pub fn open(config: &CacheConfig, path: &Path) -> Result<Self, String> {
+        let (disk, entries) = DiskCache::open(path)?;
+        let memory = ResponseCache::build(config);
+
+        let loaded = entries
+            .into_iter()
+            .filter(|(cache_key, cache)| memory.insert(*cache_key, cache.clone()).is_ok())
+            .count();
+
+        log::info!(
+            "{} cached responses loaded from {}",
+            loaded,
+            path.to_string_lossy()
+        );
+
+        Ok(DiskBackend {
+            memory: Arc::new(memory),
+            disk: Arc::new(disk),
+        })
+    }
+
+    async fn run_blocking<F, T>(task: F) -> Result<T, String>
+    where
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+        T: Send + 'static,
+    {
+        web::block(task).await.map_err(|err| match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => "Disk cache task was canceled".to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl CacheBackend for DiskBackend {
+    async fn get(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        self.memory.get(cache_key)
+    }
+
+    async fn peek(&self, cache_key: &CacheKey) -> Option<CachedResponse> {
+        self.memory.peek(cache_key)
+    }
+
+    // Serialized before the insert takes the element, but only written to
+    // disk once the memory cache has accepted it
+    async fn insert(
+        &self,
+        cache_key: CacheKey,
+        cache: CachedElement<APIResponse>,
+    ) -> Result<(), String> {
+        let mut record = Vec::new();
+        DiskCache::serialize_record(&mut record, cache_key, &cache)?;
+
+        self.memory.insert(cache_key, cache)?;
+
+        let disk = self.disk.clone();
+        if let Err(err) = DiskBackend::run_blocking(move || disk.append(&record)).await {
+            log::warn!("Failed to write api response to disk cache - {}", err);
+        }
+
+        Ok(())
+    }
+
+    async fn sweep(&self) -> usize {
+        let swept = self.memory.sweep();
+
+        if self
+            .disk
+            .needs_compaction(self.memory.stats().entries.unwrap_or_default())
+        {
+            let (disk, memory) = (self.disk.clone(), self.memory.clone());
+            let compaction = move || disk.compact(|| memory.entries());
+
+            match DiskBackend::run_blocking(compaction).await {
+                Ok(records) => log::debug!("Disk cache compacted to {} records", records),
+                Err(err) => log::warn!("Failed to compact disk cache - {}", err),
+            }
+        }
+
+        swept
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.memory.stats()
+    }
+}
+
+#[cfg(test)]
+mod test_disk_cache {
+    use super::*;
+
+    use crate::cache::CacheTtl;
+    use crate::models::{request::RequestType, state::CacheLocation};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "weather-retrieve-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn cache_key(city_id: u32) -> CacheKey {
+        CacheKey::from(CacheLocation::City(city_id), RequestType::CurrentWeather)
+    }
+
+    fn append(disk_cache: &DiskCache, key: CacheKey, cache: &CachedElement<APIResponse>) {
+        let mut record = Vec::new();
+        DiskCache::serialize_record(&mut record, key, cache).unwrap();
+
+        disk_cache.append(&record).unwrap();
+    }
+
+    fn response() -> APIResponse {
+        serde_json::from_str(include_str!("../tests/fixtures/onecall.json")).unwrap()
+    }
+
+    #[test]
+    fn check_persistence() {
+        let path = temp_path("persistence");
+
+        let (disk_cache, entries) = DiskCache::open(&path).unwrap();
+        assert!(entries.is_empty());
+
+        let ttl = CacheTtl::from_secs(600, 3_600);
+
+        append(
+            &disk_cache,
+            cache_key(1),
+            &CachedElement::new(response(), ttl),
+        );
+        append(
+            &disk_cache,
+            cache_key(2),
+            &CachedElement::new(response(), CacheTtl::from(0, 0)),
+        );
+        append(
+            &disk_cache,
+            cache_key(3),
+            &CachedElement::new_permanent(response()),
+        );
+        drop(disk_cache);
+
+        let (_, entries) = DiskCache::open(&path).unwrap();
+
+        // The expired entry is dropped when loading
+        assert_eq!(entries.len(), 2);
+
+        let (_, cache) = entries
+            .iter()
+            .find(|(key, _)| *key == cache_key(1))
+            .unwrap();
+        assert!(!cache.is_stale());
+
+        // Responses read back from disk keep all their data
+        let current = cache.element.current.as_ref().unwrap();
+        assert_eq!(current.conditions.as_ref().unwrap()[0].condition, "Rain");
+        assert_eq!(
+            serde_json::to_string(&cache.element).unwrap(),
+            serde_json::to_string(&response()).unwrap()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_corruption_tolerance() {
+        let path = temp_path("corruption");
+
+        let (disk_cache, _) = DiskCache::open(&path).unwrap();
+
+        let ttl = CacheTtl::from_secs(600, 3_600);
+        append(
+            &disk_cache,
+            cache_key(1),
+            &CachedElement::new(response(), ttl),
+        );
+        drop(disk_cache);
+
+        // A record cut short, as left by a crash in the middle of a write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"garbage\n{\"key\":{\"location\":{\"City\":2}")
+            .unwrap();
+        drop(file);
+
+        let (disk_cache, entries) = DiskCache::open(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        // Opening rewrites the file without the corrupted records
+        append(
+            &disk_cache,
+            cache_key(3),
+            &CachedElement::new(response(), ttl),
+        );
+        drop(disk_cache);
+
+        let (_, entries) = DiskCache::open(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_compaction() {
+        let path = temp_path("compaction");
+
+        let (disk_cache, _) = DiskCache::open(&path).unwrap();
+
+        let ttl = CacheTtl::from_secs(600, 3_600);
+        for _ in 0..DiskCache::MIN_COMPACTION_RECORDS {
+            append(
+                &disk_cache,
+                cache_key(1),
+                &CachedElement::new(APIResponse::default(), ttl),
+            );
+        }
+
+        assert!(disk_cache.needs_compaction(1));
+        assert!(!disk_cache.needs_compaction(DiskCache::MIN_COMPACTION_RECORDS));
+
+        let compacted = disk_cache
+            .compact(|| {
+                vec![(
+                    cache_key(1),
+                    CachedElement::new(APIResponse::default(), ttl),
+                )]
+            })
+            .unwrap();
+
+        assert_eq!(compacted, 1);
+        assert!(!disk_cache.needs_compaction(1));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn check_backend_insert() {
+        let path = temp_path("backend");
+
+        let backend = DiskBackend::open(&CacheConfig::default(), &path).unwrap();
+
+        let ttl = CacheTtl::from_secs(600, 3_600);
+        backend
+            .insert(cache_key(1), CachedElement::new(response(), ttl))
+            .await
+            .unwrap();
+
+        // Rejected by the memory cache, so nothing is written for it
+        assert!(backend
+            .insert(cache_key(1), CachedElement::new(response(), ttl))
+            .await
+            .is_err());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
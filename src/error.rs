@@ -0,0 +1,148 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::models::request::RequestResponse;
+
+// Failures served to the clients, each with its own status and a stable code
+// in the response, the message is only meant to be read by people
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiError {
+    InvalidRequest(String),
+    LocationNotFound(String),
+    // Our own budget of upstream calls per minute is spent, retrying shortly may succeed
+    RateLimited(String),
+    // The upstream replied with an error or with a response that could not be read
+    UpstreamError(String),
+    UpstreamUnavailable(String),
+    UpstreamTimeout(String),
+    // Our own budget of upstream calls is spent, the upstream isn't even reached
+    QuotaExhausted(String),
+    // The configured weather provider has no data of the requested type
+    Unsupported(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::LocationNotFound(_) => "location_not_found",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::UpstreamError(_) => "upstream_error",
+            ApiError::UpstreamUnavailable(_) => "upstream_unavailable",
+            ApiError::UpstreamTimeout(_) => "upstream_timeout",
+            ApiError::QuotaExhausted(_) => "quota_exhausted",
+            ApiError::Unsupported(_) => "unsupported_request",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::InvalidRequest(msg)
+            | ApiError::LocationNotFound(msg)
+            | ApiError::RateLimited(msg)
+            | ApiError::UpstreamError(msg)
+            | ApiError::UpstreamUnavailable(msg)
+            | ApiError::UpstreamTimeout(msg)
+            | ApiError::QuotaExhausted(msg)
+            | ApiError::Unsupported(msg) => msg,
+        }
+    }
+
+    // Failures of the upstream rather than of the request, another provider may still serve it
+    pub fn is_upstream_failure(&self) -> bool {
+        !matches!(
+            self,
+            ApiError::InvalidRequest(_) | ApiError::LocationNotFound(_)
+        )
+    }
+
+    // Maps the code of an upstream error reply. A rejected or rate limited API key is our
+    // problem and not the client's, so it is reported as an upstream failure
+    pub fn from_upstream_code(cod: u32, message: Option<String>) -> Self {
+        let message = message.unwrap_or_else(|| format!("Upstream replied with code {}", cod));
+
+        match cod {
+            400 => ApiError::InvalidRequest(message),
+            404 => ApiError::LocationNotFound(message),
+            429 => ApiError::UpstreamUnavailable(message),
+            _ => ApiError::UpstreamError(message),
+        }
+    }
+
+    // The message is supplied apart, since the one of the error includes the request url
+    pub fn from_request_error(err: &reqwest::Error, message: String) -> Self {
+        if err.is_timeout() {
+            ApiError::UpstreamTimeout(message)
+        } else if err.is_connect() {
+            ApiError::UpstreamUnavailable(message)
+        } else {
+            ApiError::UpstreamError(message)
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} - {}", self.code(), self.message())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::LocationNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::UpstreamError(_) => StatusCode::BAD_GATEWAY,
+            ApiError::UpstreamUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::QuotaExhausted(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(RequestResponse::build_failure(self))
+    }
+}
+
+#[cfg(test)]
+mod test_api_error {
+    use super::*;
+
+    use crate::test_utils::RefusingUpstream;
+
+    #[test]
+    fn check_upstream_codes() {
+        let error = ApiError::from_upstream_code(401, Some("Invalid API key".into()));
+
+        assert_eq!(error, ApiError::UpstreamError("Invalid API key".into()));
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+
+        let error = ApiError::from_upstream_code(429, None);
+
+        assert_eq!(error.code(), "upstream_unavailable");
+        assert_eq!(error.message(), "Upstream replied with code 429");
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(
+            ApiError::from_upstream_code(404, None).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            ApiError::from_upstream_code(400, None).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[actix_rt::test]
+    async fn check_network_errors() {
+        let upstream = RefusingUpstream::start();
+
+        let err = reqwest::get(&upstream.url()).await.unwrap_err();
+        let error = ApiError::from_request_error(&err, "Connection refused".into());
+
+        assert_eq!(error.code(), "upstream_unavailable");
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -1,129 +1,914 @@
-use actix_web::{get, middleware::Logger, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    get, middleware::Logger, post, web, App, Either, HttpResponse, HttpServer, Responder,
+};
 use env_logger::Env;
-use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+mod api_keys;
 mod app_state;
+mod cache;
+mod disk_cache;
+mod error;
 mod models;
+mod providers;
+mod quota;
+mod redact;
+mod redis_cache;
+mod resilience;
+#[cfg(test)]
+mod test_utils;
+mod units;
 mod utils;
 mod weather_api;
 
-use crate::models::{api::APIResponse, request::*, state::CacheKey};
+use crate::error::ApiError;
+use crate::models::{
+    api::{APIResponse, PrecipitationSummary},
+    request::*,
+    state::{CacheKey, CachedResponse},
+};
+use crate::units::UnitSelection;
 
-type SharedState = web::Data<Arc<Mutex<app_state::AppState>>>;
+type SharedState = web::Data<app_state::AppState>;
 type InboundRequest = web::Json<RequestBody>;
+// GET requests take the parameters from the query string, falling back to a JSON body
+type InboundQuery<T = RequestBody> = Either<web::Query<T>, web::Json<T>>;
 
 #[get("/weather")]
-async fn current_weather_route(data: SharedState, body: InboundRequest) -> impl Responder {
-    process_route(data, body, RequestType::CurrentWeather).await
+async fn current_weather_route(data: SharedState, query: InboundQuery) -> impl Responder {
+    process_route(data, unwrap_query(query), RequestType::CurrentWeather).await
+}
+
+#[post("/weather")]
+async fn current_weather_post_route(data: SharedState, body: InboundRequest) -> impl Responder {
+    process_route(data, body.into_inner(), RequestType::CurrentWeather).await
 }
 
 #[get("/forecast")]
-async fn weather_forecast_route(data: SharedState, body: InboundRequest) -> impl Responder {
-    process_route(data, body, RequestType::WeatherForecast).await
+async fn weather_forecast_route(data: SharedState, query: InboundQuery) -> impl Responder {
+    process_route(data, unwrap_query(query), RequestType::WeatherForecast).await
+}
+
+#[post("/forecast")]
+async fn weather_forecast_post_route(data: SharedState, body: InboundRequest) -> impl Responder {
+    process_route(data, body.into_inner(), RequestType::WeatherForecast).await
+}
+
+#[get("/forecast/daily")]
+async fn daily_forecast_route(data: SharedState, query: InboundQuery) -> impl Responder {
+    process_route(data, unwrap_query(query), RequestType::DailyForecast).await
+}
+
+#[post("/forecast/daily")]
+async fn daily_forecast_post_route(data: SharedState, body: InboundRequest) -> impl Responder {
+    process_route(data, body.into_inner(), RequestType::DailyForecast).await
+}
+
+#[get("/nowcast")]
+async fn nowcast_route(data: SharedState, query: InboundQuery) -> impl Responder {
+    process_route(data, unwrap_query(query), RequestType::Nowcast).await
+}
+
+#[post("/nowcast")]
+async fn nowcast_post_route(data: SharedState, body: InboundRequest) -> impl Responder {
+    process_route(data, body.into_inner(), RequestType::Nowcast).await
+}
+
+#[get("/alerts")]
+async fn alerts_route(data: SharedState, query: InboundQuery) -> impl Responder {
+    process_route(data, unwrap_query(query), RequestType::Alerts).await
+}
+
+#[post("/alerts")]
+async fn alerts_post_route(data: SharedState, body: InboundRequest) -> impl Responder {
+    process_route(data, body.into_inner(), RequestType::Alerts).await
+}
+
+#[get("/history")]
+async fn history_route(
+    data: SharedState,
+    query: InboundQuery<HistoryRequestBody>,
+) -> impl Responder {
+    process_history_route(data, unwrap_query(query)).await
+}
+
+#[post("/history")]
+async fn history_post_route(
+    data: SharedState,
+    body: web::Json<HistoryRequestBody>,
+) -> impl Responder {
+    process_history_route(data, body.into_inner()).await
+}
+
+async fn process_history_route(
+    data: SharedState,
+    body: HistoryRequestBody,
+) -> Result<HttpResponse, ApiError> {
+    if body.date > utils::current_epoch_day() {
+        return Err(ApiError::InvalidRequest(
+            "Historical data is not available for future dates".into(),
+        ));
+    }
+
+    process_route(data, body.request, RequestType::Historical(body.date)).await
+}
+
+#[get("/air")]
+async fn air_quality_route(
+    data: SharedState,
+    query: InboundQuery<AirRequestBody>,
+) -> impl Responder {
+    process_route(data, unwrap_query(query).into(), RequestType::AirQuality).await
+}
+
+#[post("/air")]
+async fn air_quality_post_route(
+    data: SharedState,
+    body: web::Json<AirRequestBody>,
+) -> impl Responder {
+    process_route(data, body.into_inner().into(), RequestType::AirQuality).await
+}
+
+#[get("/air/forecast")]
+async fn air_quality_forecast_route(
+    data: SharedState,
+    query: InboundQuery<AirRequestBody>,
+) -> impl Responder {
+    process_route(
+        data,
+        unwrap_query(query).into(),
+        RequestType::AirQualityForecast,
+    )
+    .await
+}
+
+#[post("/air/forecast")]
+async fn air_quality_forecast_post_route(
+    data: SharedState,
+    body: web::Json<AirRequestBody>,
+) -> impl Responder {
+    process_route(
+        data,
+        body.into_inner().into(),
+        RequestType::AirQualityForecast,
+    )
+    .await
+}
+
+fn unwrap_query<T>(query: InboundQuery<T>) -> T {
+    match query {
+        Either::A(query) => query.into_inner(),
+        Either::B(body) => body.into_inner(),
+    }
 }
 
 async fn process_route(
     data: SharedState,
-    body: InboundRequest,
+    body: RequestBody,
     request_type: RequestType,
-) -> impl Responder {
-    let mut app_state = data.lock().unwrap();
-
-    if let Some(city_keys) = app_state.get_city_keys_for_query(&body.city_query) {
-        let cache_key = CacheKey::from(city_keys.city_id, body.temperature_unit, request_type);
-
-        if app_state.has_valid_cache_for(&cache_key) {
-            let cached_response = app_state.get_cache_for(&cache_key).unwrap();
-            HttpResponse::Ok().json(RequestResponse::build_success(cached_response.to_owned()))
-        } else {
-            let api_result: Result<APIResponse, reqwest::Error>;
-
-            match request_type {
-                RequestType::CurrentWeather => {
-                    api_result = app_state
-                        .api_client
-                        .query_current_weather(
-                            city_keys.city_lat,
-                            city_keys.city_lon,
-                            body.temperature_unit,
-                        )
-                        .await;
-                }
-                RequestType::WeatherForecast => {
-                    api_result = app_state
-                        .api_client
-                        .query_forecast_weather(
-                            city_keys.city_lat,
-                            city_keys.city_lon,
-                            body.temperature_unit,
-                        )
-                        .await;
-                }
-            }
+) -> Result<HttpResponse, ApiError> {
+    let location = data.resolve_location(&body.location).ok_or_else(|| {
+        ApiError::LocationNotFound(format!(
+            "No valid location found for query {}",
+            &body.location
+        ))
+    })?;
 
-            match api_result {
-                Ok(response) => {
-                    if response.cod.is_some() && response.cod.unwrap() != 200 {
-                        HttpResponse::Ok()
-                            .json(RequestResponse::build_failure(response.message.unwrap()))
-                    } else {
-                        if let Err(msg) = app_state.cache_response(cache_key, response.clone()) {
-                            log::warn!(
-                                "Failed to created cache for ({}|{:?}|{:?}) - {}",
-                                cache_key.city_id,
-                                cache_key.temperature_fmt,
-                                cache_key.req_type,
-                                msg
-                            );
-                        }
-
-                        HttpResponse::Ok().json(RequestResponse::build_success(response))
-                    }
-                }
-                Err(err) => {
-                    HttpResponse::Ok().json(RequestResponse::build_failure(err.to_string()))
-                }
-            }
+    // Every unit system shares the same cached response, converted when it is served
+    let cache_key = CacheKey::from(location.cache_location, request_type);
+    let units = body.units;
+
+    if let Some(cached) = data.get_cache_for(&cache_key).await {
+        if cached.stale {
+            // Served right away, the refresh happens in the background
+            data.into_inner().revalidate(cache_key, location);
         }
-    } else {
-        HttpResponse::Ok().json(RequestResponse::build_failure(format!(
-            "No valid city_id found for query {}",
-            &body.city_query
-        )))
+
+        return Ok(build_success_response(cached, request_type, units));
+    }
+
+    // Nothing is locked while waiting on the upstream, so other requests keep being served
+    let cached = data
+        .into_inner()
+        .fetch_response(cache_key, location)
+        .await?;
+
+    Ok(build_success_response(cached, request_type, units))
+}
+
+fn build_success_response(
+    mut cached: CachedResponse,
+    request_type: RequestType,
+    units: UnitSelection,
+) -> HttpResponse {
+    cached.response = finalize_response(cached.response, request_type);
+    cached.response = units.convert_response(cached.response);
+
+    HttpResponse::Ok().json(RequestResponse::build_success(cached, units))
+}
+
+// Fills in the parts of the response that depend on the time it is served at
+fn finalize_response(mut response: APIResponse, request_type: RequestType) -> APIResponse {
+    let now = utils::current_epoch_secs();
+
+    match request_type {
+        RequestType::CurrentWeather => {
+            // The alert details are served by /alerts, here we only flag them
+            response.alerts_active = Some(
+                response
+                    .alerts
+                    .take()
+                    .is_some_and(|alerts| alerts.iter().any(|alert| alert.end > now)),
+            );
+        }
+        RequestType::Nowcast => {
+            response.nowcast = response
+                .minutely
+                .as_ref()
+                .map(|minutely| PrecipitationSummary::from_minutely(minutely, now));
+        }
+        _ => {}
     }
+
+    response
+}
+
+#[get("/metrics")]
+async fn metrics_route(data: SharedState) -> impl Responder {
+    HttpResponse::Ok().json(data.metrics())
+}
+
+// The server stays up while the upstream fails, so it's always served with a 200
+#[get("/health")]
+async fn health_route(data: SharedState) -> impl Responder {
+    HttpResponse::Ok().json(data.health())
+}
+
+// Expired entries of rarely queried locations would otherwise stay until evicted
+fn spawn_cache_sweeper(data: SharedState, period: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let swept = data.sweep_cache().await;
+            if swept > 0 {
+                log::debug!("Swept {} expired cache entries", swept);
+            }
+        }
+    });
+}
+
+// Malformed parameters get the same response as every other failure
+fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| ApiError::InvalidRequest(err.to_string()).into()),
+    )
+    .app_data(
+        web::JsonConfig::default()
+            .error_handler(|err, _| ApiError::InvalidRequest(err.to_string()).into()),
+    )
+    .service(metrics_route)
+    .service(health_route)
+    .service(current_weather_route)
+    .service(current_weather_post_route)
+    .service(weather_forecast_route)
+    .service(weather_forecast_post_route)
+    .service(daily_forecast_route)
+    .service(daily_forecast_post_route)
+    .service(nowcast_route)
+    .service(nowcast_post_route)
+    .service(alerts_route)
+    .service(alerts_post_route)
+    .service(history_route)
+    .service(history_post_route)
+    .service(air_quality_route)
+    .service(air_quality_post_route)
+    .service(air_quality_forecast_route)
+    .service(air_quality_forecast_post_route);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let default_filter = if utils::is_app_running_in_prod() {
+        "info"
+    } else {
+        "debug"
+    };
+    let logger =
+        env_logger::Builder::from_env(Env::default().default_filter_or(default_filter)).build();
+    let max_level = logger.filter();
+
+    // Every log line goes through the redaction of the API key first
+    log::set_boxed_logger(Box::new(redact::RedactingLogger::new(logger)))
+        .expect("Failed to set the logger");
+    log::set_max_level(max_level);
+
     if utils::is_app_running_in_prod() {
-        env_logger::from_env(Env::default().default_filter_or("info")).init();
         log::info!("Starting server in production environment...");
     } else {
-        env_logger::from_env(Env::default().default_filter_or("debug")).init();
         log::info!("Starting server in development environment...");
     }
 
-    match (utils::get_api_key(), utils::load_city_db()) {
-        (Some(api_key), Some(city_db)) => {
-            let app_state = app_state::AppState::build(api_key, city_db);
+    match (
+        utils::load_provider(),
+        utils::get_cache_config(),
+        utils::load_city_db(),
+    ) {
+        (Some(provider), Some(cache_config), Some(city_db)) => {
+            let sweep_period = Duration::from_secs(cache_config.sweep_interval_secs);
+
+            let app_state = app_state::AppState::with_provider(provider, cache_config, city_db);
 
-            let data: SharedState = web::Data::new(Arc::new(Mutex::new(app_state)));
+            let data: SharedState = web::Data::new(app_state);
+
+            spawn_cache_sweeper(data.clone(), sweep_period);
 
             HttpServer::new(move || {
                 App::new()
                     .wrap(Logger::default())
                     .wrap(Logger::new("%a %{User-Agent}i"))
                     .app_data(data.clone())
-                    .service(current_weather_route)
-                    .service(weather_forecast_route)
+                    .configure(configure_routes)
             })
             .bind("localhost:8080")?
             .run()
             .await
         }
-        (_, _) => {
+        (_, _, _) => {
             log::error!("Errors found during server initialization, shutting down...");
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod test_routes {
+    use super::*;
+
+    use actix_web::test;
+    use futures::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use crate::cache::{CacheConfig, TtlConfig};
+    use crate::models::{api::AirPollutionResponse, state::CacheLocation};
+    use crate::providers::{
+        failover::FailoverProvider, met_norway::MetNorwayProvider, ProviderConfig,
+    };
+    use crate::quota::QuotaConfig;
+    use crate::resilience::ResilienceConfig;
+    use crate::test_utils::{start_app, RefusingUpstream};
+    use crate::weather_api::{APIClient, APIConfig, APIVersion};
+
+    const UPSTREAM_DELAY_MILIS: u64 = 200;
+
+    async fn slow_onecall_fixture(calls: web::Data<AtomicUsize>) -> HttpResponse {
+        calls.fetch_add(1, Ordering::SeqCst);
+
+        actix_rt::time::delay_for(Duration::from_millis(UPSTREAM_DELAY_MILIS)).await;
+
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("../tests/fixtures/onecall.json"))
+    }
+
+    // Holds every upstream call until as many as expected are waiting on it at once
+    struct UpstreamGate {
+        calls: AtomicUsize,
+        expected: usize,
+    }
+
+    async fn gated_onecall_fixture(gate: web::Data<UpstreamGate>) -> HttpResponse {
+        gate.calls.fetch_add(1, Ordering::SeqCst);
+
+        while gate.calls.load(Ordering::SeqCst) < gate.expected {
+            actix_rt::time::delay_for(Duration::from_millis(5)).await;
+        }
+
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("../tests/fixtures/onecall.json"))
+    }
+
+    async fn rejected_key_fixture() -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .content_type("application/json")
+            .body(r#"{"cod": 401, "message": "Invalid API key."}"#)
+    }
+
+    struct TestServers {
+        // Only held so the mock upstream lives as long as the test
+        _upstream: test::TestServer,
+        srv: test::TestServer,
+        data: SharedState,
+        // Number of requests received by the mock upstream
+        calls: Arc<AtomicUsize>,
+    }
+
+    fn start_servers() -> TestServers {
+        start_servers_with_quota(QuotaConfig::default())
+    }
+
+    fn start_servers_with_quota(quota: QuotaConfig) -> TestServers {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = web::Data::from(calls.clone());
+
+        let upstream = test::start(move || {
+            App::new()
+                .app_data(upstream_calls.clone())
+                .route("/data/2.5/onecall", web::get().to(slow_onecall_fixture))
+                .route(
+                    "/data/2.5/air_pollution/forecast",
+                    web::get().to(rejected_key_fixture),
+                )
+        });
+
+        let data = mock_key_state(APIConfig {
+            base_url: upstream.url("/"),
+            version: APIVersion::V2_5,
+            quota,
+            ..APIConfig::default()
+        });
+        let srv = start_app(data.clone());
+
+        TestServers {
+            _upstream: upstream,
+            srv,
+            data,
+            calls,
+        }
+    }
+
+    fn mock_key_state(config: APIConfig) -> SharedState {
+        web::Data::new(app_state::AppState::build(
+            vec!["mock-key".into()],
+            config,
+            CacheConfig::default(),
+            vec![],
+        ))
+    }
+
+    async fn get_json(srv: &test::TestServer, path: &str) -> serde_json::Value {
+        get_with_status(srv, path).await.1
+    }
+
+    async fn get_with_status(srv: &test::TestServer, path: &str) -> (u16, serde_json::Value) {
+        let response = reqwest::get(&srv.url(path)).await.unwrap();
+
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    // Each request uses different coordinates so every one of them is a cache miss
+    async fn run_cache_misses(srv: &test::TestServer, requests: usize) -> Duration {
+        let client = reqwest::Client::new();
+
+        let start = Instant::now();
+
+        let responses = join_all((0..requests).map(|idx| {
+            client
+                .get(&srv.url(&format!("/weather?lat={}&lon=0&units=C", idx as f32 / 10.0)))
+                .send()
+        }))
+        .await;
+
+        let elapsed = start.elapsed();
+
+        for response in responses {
+            let body = response.unwrap().text().await.unwrap();
+            assert!(body.contains(r#""success":true"#), "{}", body);
+        }
+
+        elapsed
+    }
+
+    #[actix_rt::test]
+    async fn check_concurrent_cache_misses() {
+        let requests = 8;
+
+        let gate = web::Data::new(UpstreamGate {
+            calls: AtomicUsize::new(0),
+            expected: requests,
+        });
+        let upstream_gate = gate.clone();
+
+        let upstream = test::start(move || {
+            App::new()
+                .app_data(upstream_gate.clone())
+                .route("/data/2.5/onecall", web::get().to(gated_onecall_fixture))
+        });
+
+        let srv = start_app(mock_key_state(APIConfig {
+            base_url: upstream.url("/"),
+            version: APIVersion::V2_5,
+            ..APIConfig::default()
+        }));
+
+        // The upstream only replies once every miss reached it, so none of them waited on another
+        run_cache_misses(&srv, requests).await;
+
+        assert_eq!(gate.calls.load(Ordering::SeqCst), requests);
+    }
+
+    #[actix_rt::test]
+    async fn check_request_coalescing() {
+        let servers = start_servers();
+
+        let client = reqwest::Client::new();
+
+        let responses = join_all((0..10).map(|_| {
+            client
+                .get(&servers.srv.url("/weather?lat=40.42&lon=-3.7&units=C"))
+                .send()
+        }))
+        .await;
+
+        for response in responses {
+            let body = response.unwrap().text().await.unwrap();
+            assert!(body.contains(r#""success":true"#), "{}", body);
+        }
+
+        // All of them waited on the same upstream call, which was then cached
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 1);
+
+        let body = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=C").await;
+
+        assert_eq!(body["success"], true);
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 1);
+
+        let cached_at = body["cached_at"].as_u64().unwrap();
+        let expires_at = body["expires_at"].as_u64().unwrap();
+        assert_eq!(expires_at - cached_at, TtlConfig::DEFAULT_TTL_SECS);
+
+        let metrics = get_json(&servers.srv, "/metrics").await;
+
+        assert_eq!(metrics["cache"]["entries"], 1);
+        assert_eq!(metrics["cache"]["hits"], 1);
+    }
+
+    #[actix_rt::test]
+    async fn check_local_unit_conversion() {
+        let servers = start_servers();
+
+        let metric = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=C").await;
+        let imperial = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=F").await;
+        let standard = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=K").await;
+
+        // A single upstream call serves every unit system
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 1);
+
+        let temp = |body: &serde_json::Value| body["data"]["current"]["temp"].as_f64().unwrap();
+        assert_eq!(temp(&metric), 13.52);
+        assert!((temp(&imperial) - 56.34).abs() < 0.001);
+        assert!((temp(&standard) - 286.67).abs() < 0.001);
+
+        assert_eq!(
+            metric["data"]["current"]["pressure"],
+            imperial["data"]["current"]["pressure"]
+        );
+        assert_eq!(imperial["units"]["temperature"], "°F");
+        assert_eq!(imperial["units"]["wind_speed"], "mph");
+
+        let custom = get_json(
+            &servers.srv,
+            "/weather?lat=40.42&lon=-3.7&units=temperature:C,wind_speed:km/h,distance:km",
+        )
+        .await;
+
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(temp(&custom), 13.52);
+        assert_eq!(custom["data"]["current"]["visibility"], 10.0);
+        assert_eq!(custom["units"]["wind_speed"], "km/h");
+        assert_eq!(custom["units"]["pressure"], "hPa");
+    }
+
+    #[actix_rt::test]
+    async fn check_stale_while_revalidate() {
+        let servers = start_servers();
+
+        let cache_key = CacheKey::from(
+            CacheLocation::Coordinates(4042, -370),
+            RequestType::CurrentWeather,
+        );
+        let response: APIResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/onecall.json")).unwrap();
+
+        servers.data.cache_stale_response(cache_key, response).await;
+
+        // The stale copy is served without waiting on the upstream
+        let start = Instant::now();
+        let body = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=C").await;
+
+        assert!(start.elapsed() < Duration::from_millis(UPSTREAM_DELAY_MILIS));
+        assert_eq!(body["success"], true);
+        assert_eq!(body["stale"], true);
+
+        actix_rt::time::delay_for(Duration::from_millis(UPSTREAM_DELAY_MILIS * 2)).await;
+
+        let body = get_json(&servers.srv, "/weather?lat=40.42&lon=-3.7&units=C").await;
+
+        assert_eq!(body["success"], true);
+        assert_eq!(body["stale"], false);
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn check_stale_fallback() {
+        let servers = start_servers();
+
+        // The mock upstream doesn't serve air quality, so every refresh fails
+        let cache_key = CacheKey::from(
+            CacheLocation::Coordinates(4042, -370),
+            RequestType::AirQuality,
+        );
+        let response: APIResponse = serde_json::from_str::<AirPollutionResponse>(include_str!(
+            "../tests/fixtures/air_pollution.json"
+        ))
+        .unwrap()
+        .into();
+
+        servers.data.cache_stale_response(cache_key, response).await;
+
+        for _ in 0..2 {
+            let body = get_json(&servers.srv, "/air?lat=40.42&lon=-3.7").await;
+
+            assert_eq!(body["success"], true);
+            assert_eq!(body["stale"], true);
+
+            actix_rt::time::delay_for(Duration::from_millis(50)).await;
+        }
+
+        let body = get_json(&servers.srv, "/air?lat=40.43&lon=-3.7").await;
+
+        assert_eq!(body["success"], false);
+    }
+
+    #[actix_rt::test]
+    async fn check_error_responses() {
+        let servers = start_servers();
+
+        let (status, body) =
+            get_with_status(&servers.srv, "/weather?city=Nowhere,XX&units=C").await;
+
+        assert_eq!(status, 404);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error_code"], "location_not_found");
+        assert!(body["msg"].as_str().unwrap().contains("Nowhere,XX"));
+
+        let (status, body) = get_with_status(&servers.srv, "/weather?lat=1&lon=1&units=X").await;
+
+        assert_eq!(status, 400);
+        assert_eq!(body["error_code"], "invalid_request");
+
+        let (status, body) =
+            get_with_status(&servers.srv, "/history?lat=1&lon=1&units=C&date=2999-01-01").await;
+
+        assert_eq!(status, 400);
+        assert_eq!(body["error_code"], "invalid_request");
+
+        let response = reqwest::Client::new()
+            .post(&servers.srv.url("/weather"))
+            .header("Content-Type", "application/json")
+            .body(r#"{"lat": 1}"#)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 400);
+
+        // A reply that can't be read is not the client's fault
+        let (status, body) = get_with_status(&servers.srv, "/air?lat=1&lon=1").await;
+
+        assert_eq!(status, 502);
+        assert_eq!(body["error_code"], "upstream_error");
+
+        // Neither is the upstream rejecting our key
+        let (status, body) = get_with_status(&servers.srv, "/air/forecast?lat=1&lon=1").await;
+
+        assert_eq!(status, 502);
+        assert_eq!(body["error_code"], "upstream_error");
+        assert_eq!(body["msg"], "Invalid API key.");
+
+        // The rejected key is quarantined, and there is no other one to use
+        let (status, body) = get_with_status(&servers.srv, "/air?lat=1&lon=1").await;
+
+        assert_eq!(status, 503);
+        assert_eq!(body["error_code"], "upstream_unavailable");
+        assert_eq!(body["msg"], "Every API key is quarantined");
+
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[actix_rt::test]
+    async fn check_upstream_quota() {
+        let servers = start_servers_with_quota(QuotaConfig {
+            calls_per_day: 2,
+            ..QuotaConfig::default()
+        });
+
+        for lat in &[1, 2] {
+            let body = get_json(&servers.srv, &format!("/weather?lat={}&lon=0&units=C", lat)).await;
+
+            assert_eq!(body["success"], true);
+        }
+
+        let (status, body) = get_with_status(&servers.srv, "/weather?lat=3&lon=0&units=C").await;
+
+        assert_eq!(status, 503);
+        assert_eq!(body["error_code"], "quota_exhausted");
+        assert_eq!(
+            body["msg"],
+            "The daily quota of 2 upstream calls is used up"
+        );
+        assert_eq!(servers.calls.load(Ordering::SeqCst), 2);
+
+        // Cached responses don't need the upstream, so they keep being served
+        let body = get_json(&servers.srv, "/weather?lat=1&lon=0&units=C").await;
+
+        assert_eq!(body["success"], true);
+
+        let metrics = get_json(&servers.srv, "/metrics").await;
+
+        assert_eq!(metrics["quota"]["calls_per_day"], 2);
+        assert_eq!(metrics["quota"]["calls_today"], 2);
+        assert_eq!(metrics["quota"]["remaining_today"], 0);
+        assert_eq!(metrics["quota"]["rejected"], 1);
+        assert_eq!(metrics["api_keys"][0]["calls"], 2);
+    }
+
+    #[actix_rt::test]
+    async fn check_health_route() {
+        let upstream = RefusingUpstream::start();
+
+        let srv = start_app(mock_key_state(APIConfig {
+            base_url: upstream.url(),
+            resilience: ResilienceConfig {
+                max_retries: 0,
+                failure_threshold: 1,
+                ..ResilienceConfig::default()
+            },
+            ..APIConfig::default()
+        }));
+
+        let body = get_json(&srv, "/health").await;
+
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["upstream"]["state"], "closed");
+
+        let (status, _) = get_with_status(&srv, "/weather?lat=1&lon=1&units=C").await;
+
+        assert_eq!(status, 503);
+
+        let (status, body) = get_with_status(&srv, "/health").await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["upstream"]["state"], "open");
+        assert_eq!(body["upstream"]["consecutive_failures"], 1);
+
+        // Further requests fail without waiting on the upstream
+        let (status, body) = get_with_status(&srv, "/weather?lat=2&lon=1&units=C").await;
+
+        assert_eq!(status, 503);
+        assert_eq!(body["error_code"], "upstream_unavailable");
+        assert_eq!(
+            body["msg"],
+            "OpenWeatherMap is failing, requests to it are paused"
+        );
+    }
+
+    async fn met_norway_fixture() -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("../tests/fixtures/met_norway_complete.json"))
+    }
+
+    #[actix_rt::test]
+    async fn check_provider_routes() {
+        let upstream = test::start(|| {
+            App::new().route(
+                "/weatherapi/locationforecast/2.0/complete",
+                web::get().to(met_norway_fixture),
+            )
+        });
+
+        let provider = MetNorwayProvider::build(ProviderConfig {
+            base_url: upstream.url("/"),
+            resilience: ResilienceConfig::default(),
+        });
+        let srv = start_app(web::Data::new(app_state::AppState::with_provider(
+            Box::new(provider),
+            CacheConfig::default(),
+            vec![],
+        )));
+
+        let body = get_json(&srv, "/weather?lat=59.91&lon=10.75&units=F").await;
+
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["provider"], "MET Norway");
+        // Served in the requested units, like every other provider
+        assert!((body["data"]["current"]["temp"].as_f64().unwrap() - 47.12).abs() < 0.01);
+        // MET Norway has no apparent temperature, nothing stands in for it
+        assert!(body["data"]["current"].get("feels_like").is_none());
+
+        let (status, body) = get_with_status(&srv, "/alerts?lat=59.91&lon=10.75&units=C").await;
+
+        assert_eq!(status, 501);
+        assert_eq!(body["error_code"], "unsupported_request");
+        assert_eq!(body["msg"], "MET Norway doesn't provide alerts data");
+
+        let body = get_json(&srv, "/health").await;
+
+        assert_eq!(body["provider"], "MET Norway");
+
+        let metrics = get_json(&srv, "/metrics").await;
+
+        assert!(metrics.get("quota").is_none());
+        assert!(metrics.get("api_keys").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn check_provider_failover() {
+        let refusing_upstream = RefusingUpstream::start();
+        let upstream = test::start(|| {
+            App::new().route(
+                "/weatherapi/locationforecast/2.0/complete",
+                web::get().to(met_norway_fixture),
+            )
+        });
+
+        let resilience = ResilienceConfig {
+            max_retries: 0,
+            failure_threshold: 1,
+            ..ResilienceConfig::default()
+        };
+        let primary = APIClient::build(
+            vec!["mock-key".into()],
+            APIConfig {
+                base_url: refusing_upstream.url(),
+                resilience: resilience.clone(),
+                ..APIConfig::default()
+            },
+        );
+        let fallback = MetNorwayProvider::build(ProviderConfig {
+            base_url: upstream.url("/"),
+            resilience,
+        });
+        let provider =
+            FailoverProvider::build(vec![Box::new(primary), Box::new(fallback)]).unwrap();
+
+        let srv = start_app(web::Data::new(app_state::AppState::with_provider(
+            Box::new(provider),
+            CacheConfig::default(),
+            vec![],
+        )));
+
+        // Once the primary circuit opens requests go straight to the fallback
+        for lat in 1..=2 {
+            let body = get_json(&srv, &format!("/weather?lat={}&lon=10.75&units=C", lat)).await;
+
+            assert_eq!(body["success"], true);
+            assert_eq!(body["data"]["provider"], "MET Norway");
+        }
+
+        // Air quality is only provided by the primary, so there is nothing to fail over to
+        let (status, body) = get_with_status(&srv, "/air?lat=1&lon=10.75").await;
+
+        assert_eq!(status, 503);
+        assert_eq!(body["error_code"], "upstream_unavailable");
+
+        let body = get_json(&srv, "/health").await;
+
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["provider"], "OpenWeatherMap");
+        assert_eq!(body["upstream"]["state"], "open");
+        assert_eq!(body["fallbacks"][0]["provider"], "MET Norway");
+        assert_eq!(body["fallbacks"][0]["upstream"]["state"], "closed");
+
+        let metrics = get_json(&srv, "/metrics").await;
+
+        assert_eq!(metrics["providers"][0]["failovers"], 2);
+        assert_eq!(metrics["providers"][1]["served"], 2);
+        assert_eq!(metrics["quota"]["calls_today"], 1);
+    }
+
+    #[actix_rt::test]
+    #[ignore]
+    async fn bench_concurrent_cache_misses() {
+        for requests in &[1, 10, 50, 100, 200] {
+            // A fresh cache for each round, so every request is a miss
+            let servers = start_servers();
+            let elapsed = run_cache_misses(&servers.srv, *requests).await;
+
+            println!(
+                "{:>4} concurrent cache misses in {:>8.2?} - {:>7.1} req/s",
+                requests,
+                elapsed,
+                *requests as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,12 +1,23 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Deserialize, Serialize, Clone)]
+use crate::models::request::{deserialize_number, parse_date};
+use crate::models::weather::{CurrentWeather, DailyWeather, HourlyWeather, WeatherReport};
+use crate::utils;
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct APIResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lat: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lon: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    // Shift in seconds from UTC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone_offset: Option<i32>,
+    // Error replies send the status code as a string on some endpoints
+    #[serde(default, deserialize_with = "deserialize_status_code")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub cod: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
@@ -14,49 +25,864 @@ pub struct APIResponse {
     pub current: Option<WeatherCurrent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hourly: Option<Vec<WeatherHourly>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<Vec<WeatherDaily>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minutely: Option<Vec<WeatherMinutely>>,
+    // Derived from `minutely` when building the nowcast response, never sent by the upstream
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nowcast: Option<PrecipitationSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<Vec<WeatherAlert>>,
+    // Derived from `alerts` when building the current weather response
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alerts_active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub air_quality: Option<Vec<AirQuality>>,
+    // Weather provider the data was retrieved from, cached along with it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+// Responses are served and cached in the OpenWeatherMap format, whatever the provider
+impl From<WeatherReport> for APIResponse {
+    fn from(report: WeatherReport) -> Self {
+        APIResponse {
+            lat: report.lat,
+            lon: report.lon,
+            timezone: report.timezone,
+            timezone_offset: report.timezone_offset,
+            current: report.current.map(WeatherCurrent::from),
+            hourly: report
+                .hourly
+                .map(|hourly| hourly.into_iter().map(WeatherHourly::from).collect()),
+            daily: report
+                .daily
+                .map(|daily| daily.into_iter().map(WeatherDaily::from).collect()),
+            minutely: report.minutely,
+            alerts: report.alerts,
+            air_quality: report.air_quality,
+            provider: report.provider,
+            ..Default::default()
+        }
+    }
+}
+
+// Replies of the OpenWeatherMap API, once the client checked they aren't an error
+impl From<APIResponse> for WeatherReport {
+    fn from(response: APIResponse) -> Self {
+        WeatherReport {
+            lat: response.lat,
+            lon: response.lon,
+            timezone: response.timezone,
+            timezone_offset: response.timezone_offset,
+            current: response.current.map(CurrentWeather::from),
+            hourly: response
+                .hourly
+                .map(|hourly| hourly.into_iter().map(HourlyWeather::from).collect()),
+            daily: response
+                .daily
+                .map(|daily| daily.into_iter().map(DailyWeather::from).collect()),
+            minutely: response.minutely,
+            alerts: response.alerts,
+            air_quality: response.air_quality,
+            provider: response.provider,
+        }
+    }
+}
+
+impl From<AirPollutionResponse> for APIResponse {
+    fn from(response: AirPollutionResponse) -> Self {
+        APIResponse {
+            lat: response.coord.as_ref().map(|coord| coord.lat),
+            lon: response.coord.as_ref().map(|coord| coord.lon),
+            cod: response.cod,
+            message: response.message,
+            air_quality: response.list,
+            ..Default::default()
+        }
+    }
+}
+
+// Fields the upstream leaves out depending on the location, time or endpoint are optional
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct WeatherCurrent {
     pub dt: u32,
-    pub sunrise: u32,
-    pub sunset: u32,
+    // Not sent during polar day or night, nor by the providers without the times of the sun
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunrise: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<u32>,
     pub temp: f32,
-    pub feels_like: f32,
-    pub pressure: u32,
+    // Not every provider computes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feels_like: Option<f32>,
+    // In hPa
+    pub pressure: f32,
     pub humidity: u32,
-    pub dew_point: f32,
-    pub uvi: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uvi: Option<f32>,
     pub clouds: u32,
-    pub visibility: u32,
+    // In meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<f32>,
     pub wind_speed: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_gust: Option<f32>,
     pub wind_deg: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename(deserialize = "weather"))]
+    pub rain: Option<PrecipitationVolume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snow: Option<PrecipitationVolume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(deserialize = "weather"), alias = "conditions")]
     pub conditions: Option<Vec<WeatherCondition>>,
 }
 
+impl From<CurrentWeather> for WeatherCurrent {
+    fn from(current: CurrentWeather) -> Self {
+        WeatherCurrent {
+            dt: current.dt,
+            sunrise: current.sunrise,
+            sunset: current.sunset,
+            temp: current.temperature,
+            feels_like: current.feels_like,
+            pressure: current.pressure,
+            humidity: current.humidity,
+            dew_point: current.dew_point,
+            uvi: current.uv_index,
+            clouds: current.clouds,
+            visibility: current.visibility,
+            wind_speed: current.wind_speed,
+            wind_gust: current.wind_gust,
+            wind_deg: current.wind_direction,
+            rain: PrecipitationVolume::from_amount(current.rain),
+            snow: PrecipitationVolume::from_amount(current.snow),
+            conditions: Some(current.conditions).filter(|conditions| !conditions.is_empty()),
+        }
+    }
+}
+
+impl From<WeatherCurrent> for CurrentWeather {
+    fn from(current: WeatherCurrent) -> Self {
+        CurrentWeather {
+            dt: current.dt,
+            sunrise: current.sunrise,
+            sunset: current.sunset,
+            temperature: current.temp,
+            feels_like: current.feels_like,
+            pressure: current.pressure,
+            humidity: current.humidity,
+            dew_point: current.dew_point,
+            uv_index: current.uvi,
+            clouds: current.clouds,
+            visibility: current.visibility,
+            wind_speed: current.wind_speed,
+            wind_gust: current.wind_gust,
+            wind_direction: current.wind_deg,
+            rain: current.rain.map(|volume| volume.one_hour),
+            snow: current.snow.map(|volume| volume.one_hour),
+            conditions: current.conditions.unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct WeatherCondition {
-    #[serde(rename(deserialize = "main"))]
+    pub id: u32,
+    #[serde(rename(deserialize = "main"), alias = "condition")]
     pub condition: String,
     pub description: String,
+    pub icon: String,
+}
+
+// Precipitation volume in mm for the last hour
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PrecipitationVolume {
+    #[serde(rename = "1h")]
+    pub one_hour: f32,
+}
+
+impl PrecipitationVolume {
+    // Hours without any precipitation have no volume
+    fn from_amount(amount: Option<f32>) -> Option<Self> {
+        amount
+            .filter(|amount| *amount > 0.0)
+            .map(|one_hour| PrecipitationVolume { one_hour })
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct WeatherHourly {
     pub dt: u32,
     pub temp: f32,
-    pub feels_like: f32,
-    pub pressure: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feels_like: Option<f32>,
+    // In hPa
+    pub pressure: f32,
+    pub humidity: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uvi: Option<f32>,
+    pub clouds: u32,
+    // In meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<f32>,
+    pub wind_speed: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_gust: Option<f32>,
+    pub wind_deg: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rain: Option<PrecipitationVolume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snow: Option<PrecipitationVolume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(deserialize = "weather"), alias = "conditions")]
+    pub conditions: Option<Vec<WeatherCondition>>,
+    // Only present on forecasts, historical observations don't have it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pop: Option<f32>,
+}
+
+impl From<HourlyWeather> for WeatherHourly {
+    fn from(hour: HourlyWeather) -> Self {
+        WeatherHourly {
+            dt: hour.dt,
+            temp: hour.temperature,
+            feels_like: hour.feels_like,
+            pressure: hour.pressure,
+            humidity: hour.humidity,
+            dew_point: hour.dew_point,
+            uvi: hour.uv_index,
+            clouds: hour.clouds,
+            visibility: hour.visibility,
+            wind_speed: hour.wind_speed,
+            wind_gust: hour.wind_gust,
+            wind_deg: hour.wind_direction,
+            rain: PrecipitationVolume::from_amount(hour.rain),
+            snow: PrecipitationVolume::from_amount(hour.snow),
+            conditions: Some(hour.conditions).filter(|conditions| !conditions.is_empty()),
+            pop: hour.pop,
+        }
+    }
+}
+
+impl From<WeatherHourly> for HourlyWeather {
+    fn from(hour: WeatherHourly) -> Self {
+        HourlyWeather {
+            dt: hour.dt,
+            temperature: hour.temp,
+            feels_like: hour.feels_like,
+            pressure: hour.pressure,
+            humidity: hour.humidity,
+            dew_point: hour.dew_point,
+            uv_index: hour.uvi,
+            clouds: hour.clouds,
+            visibility: hour.visibility,
+            wind_speed: hour.wind_speed,
+            wind_gust: hour.wind_gust,
+            wind_direction: hour.wind_deg,
+            rain: hour.rain.map(|volume| volume.one_hour),
+            snow: hour.snow.map(|volume| volume.one_hour),
+            conditions: hour.conditions.unwrap_or_default(),
+            pop: hour.pop,
+        }
+    }
+}
+
+fn deserialize_status_code<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_number(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WeatherDaily {
+    pub dt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunrise: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<u32>,
+    // Only OpenWeatherMap provides them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moonrise: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moonset: Option<u32>,
+    // 0 and 1 are new moon, 0.5 is full moon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moon_phase: Option<f32>,
+    pub temp: DailyTemperature,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feels_like: Option<DailyFeelsLike>,
+    // In hPa
+    pub pressure: f32,
     pub humidity: u32,
-    pub dew_point: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uvi: Option<f32>,
     pub clouds: u32,
-    pub visibility: u32,
     pub wind_speed: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_gust: Option<f32>,
     pub wind_deg: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename(deserialize = "weather"))]
+    #[serde(rename(deserialize = "weather"), alias = "conditions")]
     pub conditions: Option<Vec<WeatherCondition>>,
-    pub pop: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pop: Option<f32>,
+    // Precipitation volume for the day in mm, only present when expected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rain: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snow: Option<f32>,
+}
+
+impl From<DailyWeather> for WeatherDaily {
+    fn from(day: DailyWeather) -> Self {
+        WeatherDaily {
+            dt: day.dt,
+            sunrise: day.sunrise,
+            sunset: day.sunset,
+            moonrise: day.moonrise,
+            moonset: day.moonset,
+            moon_phase: day.moon_phase,
+            temp: day.temperature,
+            feels_like: day.feels_like,
+            pressure: day.pressure,
+            humidity: day.humidity,
+            dew_point: day.dew_point,
+            uvi: day.uv_index,
+            clouds: day.clouds,
+            wind_speed: day.wind_speed,
+            wind_gust: day.wind_gust,
+            wind_deg: day.wind_direction,
+            conditions: Some(day.conditions).filter(|conditions| !conditions.is_empty()),
+            pop: day.pop,
+            rain: day.rain.filter(|rain| *rain > 0.0),
+            snow: day.snow.filter(|snow| *snow > 0.0),
+        }
+    }
+}
+
+impl From<WeatherDaily> for DailyWeather {
+    fn from(day: WeatherDaily) -> Self {
+        DailyWeather {
+            dt: day.dt,
+            sunrise: day.sunrise,
+            sunset: day.sunset,
+            moonrise: day.moonrise,
+            moonset: day.moonset,
+            moon_phase: day.moon_phase,
+            temperature: day.temp,
+            feels_like: day.feels_like,
+            pressure: day.pressure,
+            humidity: day.humidity,
+            dew_point: day.dew_point,
+            uv_index: day.uvi,
+            clouds: day.clouds,
+            wind_speed: day.wind_speed,
+            wind_gust: day.wind_gust,
+            wind_direction: day.wind_deg,
+            conditions: day.conditions.unwrap_or_default(),
+            pop: day.pop,
+            rain: day.rain,
+            snow: day.snow,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DailyTemperature {
+    pub min: f32,
+    pub max: f32,
+    pub morn: f32,
+    pub day: f32,
+    pub eve: f32,
+    pub night: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DailyFeelsLike {
+    pub morn: f32,
+    pub day: f32,
+    pub eve: f32,
+    pub night: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WeatherAlert {
+    #[serde(rename(deserialize = "sender_name"), alias = "sender")]
+    pub sender: String,
+    pub event: String,
+    pub start: u32,
+    pub end: u32,
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WeatherMinutely {
+    pub dt: u32,
+    // Precipitation volume in mm/h
+    pub precipitation: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct PrecipitationSummary {
+    pub raining: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starts_in: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stops_in: Option<u32>,
+    pub summary: String,
+}
+
+impl PrecipitationSummary {
+    pub fn from_minutely(minutely: &[WeatherMinutely], now: u32) -> Self {
+        // Minutes that already ended are not part of the nowcast anymore
+        let upcoming = minutely
+            .iter()
+            .skip_while(|minute| minute.dt + 60 <= now)
+            .collect::<Vec<&WeatherMinutely>>();
+
+        let minutes_until = |minute: &WeatherMinutely| minute.dt.saturating_sub(now).div_ceil(60);
+
+        let raining = upcoming
+            .first()
+            .is_some_and(|minute| minute.precipitation > 0.0);
+
+        let starts_at = if raining {
+            None
+        } else {
+            upcoming
+                .iter()
+                .position(|minute| minute.precipitation > 0.0)
+        };
+
+        let stops_in = upcoming
+            .iter()
+            .skip(starts_at.unwrap_or(0))
+            .find(|minute| minute.precipitation <= 0.0)
+            .filter(|_| raining || starts_at.is_some())
+            .map(|minute| minutes_until(minute));
+
+        let starts_in = starts_at.map(|idx| minutes_until(upcoming[idx]));
+
+        let summary = match (raining, starts_in, stops_in) {
+            (true, _, Some(stops_in)) => format!("Rain stops in {} minutes", stops_in),
+            (true, _, None) => "Rain for the next hour".to_owned(),
+            (false, Some(starts_in), _) => format!("Rain starts in {} minutes", starts_in),
+            (false, None, _) => "No rain expected in the next hour".to_owned(),
+        };
+
+        PrecipitationSummary {
+            raining,
+            starts_in,
+            stops_in,
+            summary,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AirPollutionResponse {
+    pub coord: Option<Coordinates>,
+    pub list: Option<Vec<AirQuality>>,
+    #[serde(default, deserialize_with = "deserialize_status_code")]
+    pub cod: Option<u32>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Coordinates {
+    pub lat: f32,
+    pub lon: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AirQuality {
+    pub dt: u32,
+    #[serde(rename(deserialize = "main"), alias = "index")]
+    pub index: AirQualityIndex,
+    pub components: AirComponents,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AirQualityIndex {
+    // From 1 (good) to 5 (very poor)
+    pub aqi: u32,
+}
+
+// Concentrations in μg/m3
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AirComponents {
+    pub co: f32,
+    pub no: f32,
+    pub no2: f32,
+    pub o3: f32,
+    pub so2: f32,
+    pub pm2_5: f32,
+    pub pm10: f32,
+    pub nh3: f32,
+}
+
+// Reply of the 3.0 day summary, which aggregates the observations of a whole date
+#[derive(Deserialize)]
+pub struct DaySummaryResponse {
+    pub lat: Option<f32>,
+    pub lon: Option<f32>,
+    // Shift from UTC, e.g. +01:00
+    pub tz: Option<String>,
+    pub date: Option<String>,
+    // Left out by error replies
+    #[serde(flatten)]
+    pub summary: Option<DaySummary>,
+    #[serde(default, deserialize_with = "deserialize_status_code")]
+    pub cod: Option<u32>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DaySummary {
+    pub cloud_cover: AfternoonValue,
+    pub humidity: AfternoonValue,
+    pub precipitation: PrecipitationTotal,
+    pub temperature: DaySummaryTemperature,
+    pub pressure: AfternoonValue,
+    pub wind: DaySummaryWind,
+}
+
+#[derive(Deserialize)]
+pub struct AfternoonValue {
+    pub afternoon: f32,
+}
+
+#[derive(Deserialize)]
+pub struct PrecipitationTotal {
+    pub total: f32,
+}
+
+#[derive(Deserialize)]
+pub struct DaySummaryTemperature {
+    pub min: f32,
+    pub max: f32,
+    pub morning: f32,
+    pub afternoon: f32,
+    pub evening: f32,
+    pub night: f32,
+}
+
+#[derive(Deserialize)]
+pub struct DaySummaryWind {
+    pub max: DaySummaryWindMax,
+}
+
+#[derive(Deserialize)]
+pub struct DaySummaryWindMax {
+    pub speed: f32,
+    pub direction: f32,
+}
+
+impl From<DaySummaryResponse> for APIResponse {
+    fn from(response: DaySummaryResponse) -> Self {
+        let day = response.date.as_deref().and_then(parse_date);
+
+        let daily = match (day, response.summary) {
+            (Some(day), Some(summary)) => Some(vec![WeatherDaily {
+                dt: day * utils::SECONDS_PER_DAY,
+                sunrise: None,
+                sunset: None,
+                moonrise: None,
+                moonset: None,
+                moon_phase: None,
+                temp: DailyTemperature {
+                    min: summary.temperature.min,
+                    max: summary.temperature.max,
+                    morn: summary.temperature.morning,
+                    day: summary.temperature.afternoon,
+                    eve: summary.temperature.evening,
+                    night: summary.temperature.night,
+                },
+                feels_like: None,
+                pressure: summary.pressure.afternoon,
+                humidity: summary.humidity.afternoon.round() as u32,
+                dew_point: None,
+                uvi: None,
+                clouds: summary.cloud_cover.afternoon.round() as u32,
+                wind_speed: summary.wind.max.speed,
+                wind_gust: None,
+                wind_deg: summary.wind.max.direction.round() as u32,
+                conditions: None,
+                pop: None,
+                // Rain and snow aren't told apart, so the whole of it is reported as rain
+                rain: Some(summary.precipitation.total).filter(|total| *total > 0.0),
+                snow: None,
+            }]),
+            _ => None,
+        };
+
+        APIResponse {
+            lat: response.lat,
+            lon: response.lon,
+            timezone_offset: response.tz.as_deref().and_then(parse_utc_offset),
+            cod: response.cod,
+            message: response.message,
+            daily,
+            ..Default::default()
+        }
+    }
+}
+
+// Parses shifts from UTC in the +HH:MM form into seconds
+fn parse_utc_offset(tz: &str) -> Option<i32> {
+    let (sign, offset) = match tz.chars().next()? {
+        '+' => (1, &tz[1..]),
+        '-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+
+    let mut parts = offset.splitn(2, ':');
+    let hours: i32 = parts.next()?.parse().ok()?;
+    let minutes: i32 = parts.next().unwrap_or("0").parse().ok()?;
+
+    Some(sign * (hours * 3_600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod test_precipitation_summary {
+    use super::*;
+
+    fn build_series(start: u32, precipitation: &[f32]) -> Vec<WeatherMinutely> {
+        precipitation
+            .iter()
+            .enumerate()
+            .map(|(idx, precipitation)| WeatherMinutely {
+                dt: start + idx as u32 * 60,
+                precipitation: *precipitation,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_no_rain() {
+        let series = build_series(1000, &[0.0; 60]);
+        let summary = PrecipitationSummary::from_minutely(&series, 1000);
+
+        assert!(!summary.raining);
+        assert_eq!(summary.starts_in, None);
+        assert_eq!(summary.stops_in, None);
+    }
+
+    #[test]
+    fn check_rain_starting_and_stopping() {
+        let mut precipitation = vec![0.0; 60];
+        precipitation[10..25].iter_mut().for_each(|p| *p = 0.5);
+
+        let series = build_series(1000, &precipitation);
+        let summary = PrecipitationSummary::from_minutely(&series, 1000);
+
+        assert!(!summary.raining);
+        assert_eq!(summary.starts_in, Some(10));
+        assert_eq!(summary.stops_in, Some(25));
+        assert_eq!(summary.summary, "Rain starts in 10 minutes");
+    }
+
+    #[test]
+    fn check_rain_stopping() {
+        let mut precipitation = vec![0.0; 60];
+        precipitation[..5].iter_mut().for_each(|p| *p = 1.2);
+
+        let series = build_series(1000, &precipitation);
+
+        // Two minutes into the series the first two entries are already past
+        let summary = PrecipitationSummary::from_minutely(&series, 1120);
+
+        assert!(summary.raining);
+        assert_eq!(summary.starts_in, None);
+        assert_eq!(summary.stops_in, Some(3));
+        assert_eq!(summary.summary, "Rain stops in 3 minutes");
+
+        let series = build_series(1000, &[1.2; 60]);
+        let summary = PrecipitationSummary::from_minutely(&series, 1000);
+
+        assert!(summary.raining);
+        assert_eq!(summary.stops_in, None);
+    }
+}
+
+#[cfg(test)]
+mod test_api_models {
+    use super::*;
+
+    #[test]
+    fn check_full_response() {
+        let response: APIResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/onecall.json")).unwrap();
+
+        assert_eq!(response.timezone.as_deref(), Some("Europe/Madrid"));
+        assert_eq!(response.timezone_offset, Some(3600));
+
+        let current = response.current.unwrap();
+
+        assert_eq!(current.visibility, Some(10000.0));
+        assert_eq!(current.wind_gust, Some(9.26));
+        assert_eq!(current.rain.unwrap().one_hour, 0.21);
+        assert!(current.snow.is_none());
+
+        let condition = &current.conditions.unwrap()[0];
+
+        assert_eq!(condition.id, 500);
+        assert_eq!(condition.condition, "Rain");
+        assert_eq!(condition.icon, "10d");
+
+        assert_eq!(response.minutely.unwrap().len(), 3);
+
+        let hourly = response.hourly.unwrap();
+
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[0].pop, Some(0.48));
+        assert_eq!(hourly[1].snow.as_ref().unwrap().one_hour, 0.1);
+
+        let daily = response.daily.unwrap();
+
+        assert_eq!(daily[0].temp.max, 15.89);
+        assert_eq!(daily[0].moon_phase, Some(0.25));
+        assert_eq!(daily[0].rain, Some(2.51));
+
+        let alerts = response.alerts.unwrap();
+
+        assert_eq!(alerts[0].sender, "AEMET");
+        assert_eq!(alerts[0].tags, vec!["Wind"]);
+    }
+
+    #[test]
+    fn check_report_conversion() {
+        let response: APIResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/onecall.json")).unwrap();
+        let served = serde_json::to_value(&response).unwrap();
+
+        // Nothing is lost mapping a reply to a report and serving it back
+        let report = WeatherReport::from(response);
+
+        assert_eq!(report.current.as_ref().unwrap().rain, Some(0.21));
+        assert_eq!(
+            serde_json::to_value(APIResponse::from(report)).unwrap(),
+            served
+        );
+
+        let report = WeatherReport {
+            current: Some(CurrentWeather {
+                rain: Some(0.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let current = APIResponse::from(report).current.unwrap();
+
+        assert!(current.rain.is_none());
+        assert!(current.feels_like.is_none());
+        assert!(current.conditions.is_none());
+    }
+
+    #[test]
+    fn check_missing_optional_fields() {
+        let response: APIResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/onecall_sparse.json")).unwrap();
+
+        let current = response.current.unwrap();
+
+        assert!(current.sunrise.is_none());
+        assert!(current.visibility.is_none());
+        assert!(current.wind_gust.is_none());
+        assert!(current.rain.is_none());
+
+        let hourly = response.hourly.unwrap();
+
+        assert!(hourly[0].visibility.is_none());
+        assert!(hourly[0].uvi.is_none());
+    }
+
+    #[test]
+    fn check_historical_response() {
+        let response: APIResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/timemachine.json")).unwrap();
+
+        assert!(response.current.is_some());
+
+        let hourly = response.hourly.unwrap();
+
+        assert_eq!(hourly.len(), 2);
+        assert!(hourly.iter().all(|hour| hour.pop.is_none()));
+    }
+
+    #[test]
+    fn check_air_pollution_response() {
+        let response: AirPollutionResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/air_pollution.json")).unwrap();
+        let response = APIResponse::from(response);
+
+        assert_eq!(response.lat, Some(40.42));
+
+        let air_quality = response.air_quality.unwrap();
+
+        assert_eq!(air_quality[0].index.aqi, 2);
+        assert_eq!(air_quality[0].components.pm2_5, 11.38);
+    }
+
+    #[test]
+    fn check_day_summary_response() {
+        let response: DaySummaryResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/day_summary.json")).unwrap();
+        let response = APIResponse::from(response);
+
+        assert_eq!(response.timezone_offset, Some(3_600));
+        assert!(response.hourly.is_none());
+
+        let daily = response.daily.unwrap();
+
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].dt, 1_604_275_200);
+        assert_eq!(daily[0].temp.max, 15.21);
+        assert_eq!(daily[0].temp.day, 14.02);
+        assert_eq!(daily[0].wind_deg, 230);
+        assert_eq!(daily[0].rain, Some(1.4));
+
+        let response: DaySummaryResponse = serde_json::from_str(
+            r#"{"cod": 400, "message": "The date is out of the allowed range"}"#,
+        )
+        .unwrap();
+        let response = APIResponse::from(response);
+
+        assert_eq!(response.cod, Some(400));
+        assert!(response.daily.is_none());
+
+        assert_eq!(parse_utc_offset("-03:30"), Some(-12_600));
+        assert_eq!(parse_utc_offset("UTC"), None);
+    }
+
+    #[test]
+    fn check_error_responses() {
+        let response: APIResponse = serde_json::from_str(
+            r#"{"cod": 401, "message": "Invalid API key. Please see http://openweathermap.org/faq#error401 for more info."}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.cod, Some(401));
+        assert!(response.current.is_none());
+
+        let response: APIResponse = serde_json::from_str(
+            r#"{"cod": "400", "message": "requested time is out of allowed range of 5 days back"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.cod, Some(400));
+    }
 }
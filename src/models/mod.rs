@@ -1,3 +1,4 @@
 pub mod api;
 pub mod request;
 pub mod state;
+pub mod weather;
@@ -1,25 +1,130 @@
 use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
 
-use crate::models::api::APIResponse;
+use crate::error::ApiError;
+use crate::models::{api::APIResponse, state::CachedResponse};
+use crate::units::UnitSelection;
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+#[derive(Deserialize, Serialize, PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum RequestType {
     CurrentWeather,
     WeatherForecast,
+    DailyForecast,
+    Nowcast,
+    Alerts,
+    // Days since the unix epoch of the requested date
+    Historical(u32),
+    AirQuality,
+    AirQualityForecast,
+}
+
+impl RequestType {
+    // One request of each type, the historical one stands for every date
+    pub const VARIANTS: [RequestType; 8] = [
+        RequestType::CurrentWeather,
+        RequestType::WeatherForecast,
+        RequestType::DailyForecast,
+        RequestType::Nowcast,
+        RequestType::Alerts,
+        RequestType::Historical(0),
+        RequestType::AirQuality,
+        RequestType::AirQualityForecast,
+    ];
+
+    // Name used to refer to the request type in the configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestType::CurrentWeather => "weather",
+            RequestType::WeatherForecast => "forecast",
+            RequestType::DailyForecast => "forecast_daily",
+            RequestType::Nowcast => "nowcast",
+            RequestType::Alerts => "alerts",
+            RequestType::Historical(_) => "history",
+            RequestType::AirQuality => "air",
+            RequestType::AirQualityForecast => "air_forecast",
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct RequestBody {
-    pub city_query: String,
-    #[serde(deserialize_with = "deserialize_from_str")]
-    #[serde(rename = "units")]
-    pub temperature_unit: TemperatureFormat,
+    #[serde(flatten)]
+    pub location: LocationQuery,
+    pub units: UnitSelection,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct HistoryRequestBody {
+    #[serde(flatten)]
+    pub request: RequestBody,
+    // Days since the unix epoch, supplied as a YYYY-MM-DD date
+    #[serde(deserialize_with = "deserialize_date")]
+    pub date: u32,
+}
+
+// Air quality doesn't depend on any unit system, so only the location is needed
+#[derive(Deserialize, Serialize)]
+pub struct AirRequestBody {
+    #[serde(flatten)]
+    pub location: LocationQuery,
+}
+
+impl From<AirRequestBody> for RequestBody {
+    fn from(body: AirRequestBody) -> Self {
+        RequestBody {
+            location: body.location,
+            units: UnitSelection::METRIC,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum LocationQuery {
+    Coordinates {
+        #[serde(deserialize_with = "deserialize_number")]
+        lat: f32,
+        #[serde(deserialize_with = "deserialize_number")]
+        lon: f32,
+    },
+    CityId {
+        #[serde(deserialize_with = "deserialize_number")]
+        city_id: u32,
+    },
+    CityQuery {
+        #[serde(alias = "city")]
+        city_query: String,
+    },
+}
+
+impl Display for LocationQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            LocationQuery::Coordinates { lat, lon } => write!(f, "({},{})", lat, lon),
+            LocationQuery::CityId { city_id } => write!(f, "{}", city_id),
+            LocationQuery::CityQuery { city_query } => write!(f, "{}", city_query),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct RequestResponse {
     success: bool,
+    // Machine readable reason of a failure, see ApiError::code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
+    // Set when the data is past its freshness and is being refreshed
+    #[serde(default)]
+    stale: bool,
+    // Epochs in which the data was cached and in which it stops being fresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    // Unit of each quantity in the data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    units: Option<UnitSelection>,
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<ResponseData>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -27,19 +132,29 @@ pub struct RequestResponse {
 }
 
 impl RequestResponse {
-    pub fn build_success(api_response: APIResponse) -> Self {
+    pub fn build_success(cached: CachedResponse, units: UnitSelection) -> Self {
         RequestResponse {
             success: true,
-            data: Some(ResponseData::Success(api_response)),
+            error_code: None,
+            stale: cached.stale,
+            cached_at: cached.cached_at,
+            expires_at: cached.expires_at,
+            units: Some(units),
+            data: Some(ResponseData::Success(Box::new(cached.response))),
             msg: None,
         }
     }
 
-    pub fn build_failure(failure_msg: String) -> Self {
+    pub fn build_failure(error: &ApiError) -> Self {
         RequestResponse {
             success: false,
+            error_code: Some(error.code().to_owned()),
+            stale: false,
+            cached_at: None,
+            expires_at: None,
+            units: None,
             data: None,
-            msg: Some(failure_msg),
+            msg: Some(error.message().to_owned()),
         }
     }
 }
@@ -47,7 +162,7 @@ impl RequestResponse {
 #[derive(Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ResponseData {
-    Success(APIResponse),
+    Success(Box<APIResponse>),
     Failure(String),
 }
 
@@ -69,21 +184,218 @@ impl Display for TemperatureFormat {
     }
 }
 
-fn deserialize_from_str<'de, D>(deserializer: D) -> Result<TemperatureFormat, D::Error>
+fn deserialize_date<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+
+    parse_date(&s).ok_or_else(|| {
+        log::warn!("Invalid date parameter supplied - {}", s);
+        Error::custom("Invalid date parameter, expected YYYY-MM-DD.")
+    })
+}
+
+// Converts a YYYY-MM-DD date into days since the unix epoch
+pub(crate) fn parse_date(date: &str) -> Option<u32> {
+    let parts = date
+        .trim()
+        .split('-')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let (year, month, day) = (parts[0], parts[1], parts[2]);
+
+    let is_leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+
+    if year < 1970 || day == 0 || day > month_days {
+        return None;
+    }
+
+    // Days from civil algorithm, with years starting in March
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+// Inverse of parse_date, for the upstreams that take dates instead of epochs
+pub(crate) fn format_date(days: u32) -> String {
+    let days = days + 719_468;
+
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Query strings hand every value over as a string, so numbers are accepted in both forms
+pub(crate) fn deserialize_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
     D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
 {
-    let mut s: String = Deserialize::deserialize(deserializer)?;
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString<T> {
+        Number(T),
+        String(String),
+    }
+
+    match NumberOrString::<T>::deserialize(deserializer)? {
+        NumberOrString::Number(value) => Ok(value),
+        NumberOrString::String(s) => s
+            .trim()
+            .parse::<T>()
+            .map_err(|_| Error::custom(format!("Invalid numeric parameter - {}", s))),
+    }
+}
+
+#[cfg(test)]
+mod test_request_body {
+    use super::*;
+
+    use actix_web::web::Query;
+
+    use crate::units::{PressureUnit, SpeedUnit, TemperatureUnit};
+
+    #[test]
+    fn check_query_string_parsing() {
+        let query = Query::<RequestBody>::from_query("city=Madrid,ES&units=C").unwrap();
+
+        assert_eq!(
+            query.location,
+            LocationQuery::CityQuery {
+                city_query: "Madrid,ES".into()
+            }
+        );
+        assert_eq!(query.units, UnitSelection::METRIC);
+
+        let query = Query::<RequestBody>::from_query("city_query=Paris,FR&units=kelvin").unwrap();
 
-    s.make_ascii_lowercase();
+        assert_eq!(
+            query.location,
+            LocationQuery::CityQuery {
+                city_query: "Paris,FR".into()
+            }
+        );
+        assert_eq!(query.units, UnitSelection::STANDARD);
 
-    match s.as_ref() {
-        "f" | "fahrenheit" => Ok(TemperatureFormat::Imperial),
-        "c" | "celsius" => Ok(TemperatureFormat::Metric),
-        "k" | "kelvin" => Ok(TemperatureFormat::Standard),
-        &_ => {
-            log::warn!("Invalid temperature parameter supplied - {}", s);
-            Err(Error::custom("Invalid temperature parameter."))
+        let query = Query::<RequestBody>::from_query("lat=40.4&lon=-3.7&units=C").unwrap();
+
+        assert_eq!(
+            query.location,
+            LocationQuery::Coordinates {
+                lat: 40.4,
+                lon: -3.7
+            }
+        );
+
+        let query = Query::<RequestBody>::from_query("city_id=3117735&units=F").unwrap();
+
+        assert_eq!(query.location, LocationQuery::CityId { city_id: 3117735 });
+        assert_eq!(query.units, UnitSelection::IMPERIAL);
+
+        let query = Query::<RequestBody>::from_query(
+            "city_id=3117735&units=temperature:C,wind_speed:km/h,pressure:hPa",
+        )
+        .unwrap();
+
+        assert_eq!(query.units.wind_speed, SpeedUnit::KilometersPerHour);
+
+        assert!(Query::<RequestBody>::from_query("city=Madrid,ES&units=wind_speed:X").is_err());
+        assert!(Query::<RequestBody>::from_query("city=Madrid,ES&units=X").is_err());
+        assert!(Query::<RequestBody>::from_query("units=C").is_err());
+        assert!(Query::<RequestBody>::from_query("lat=40.4&lon=west&units=C").is_err());
+    }
+
+    #[test]
+    fn check_date_parsing() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(11_017));
+        assert_eq!(parse_date("2020-02-29"), Some(18_321));
+        assert_eq!(parse_date("2021-02-29"), None);
+        assert_eq!(parse_date("2020-13-01"), None);
+        assert_eq!(parse_date("2020-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+
+        for date in &["1970-01-01", "2000-02-29", "2020-11-02", "2021-12-31"] {
+            assert_eq!(format_date(parse_date(date).unwrap()), *date);
         }
+
+        let query =
+            Query::<HistoryRequestBody>::from_query("city=Madrid,ES&units=C&date=2020-11-02")
+                .unwrap();
+
+        assert_eq!(query.date, 18_568);
+        assert_eq!(query.request.units, UnitSelection::METRIC);
+    }
+
+    #[test]
+    fn check_json_body_parsing() {
+        let body: RequestBody =
+            serde_json::from_str(r#"{"lat": 40.4, "lon": -3.7, "units": "C"}"#).unwrap();
+
+        assert_eq!(
+            body.location,
+            LocationQuery::Coordinates {
+                lat: 40.4,
+                lon: -3.7
+            }
+        );
+
+        let body: RequestBody =
+            serde_json::from_str(r#"{"city_id": 3117735, "units": "C"}"#).unwrap();
+
+        assert_eq!(body.location, LocationQuery::CityId { city_id: 3117735 });
+
+        let body: RequestBody = serde_json::from_str(
+            r#"{"city_id": 3117735, "units": {"temperature": "F", "wind_speed": "kn", "pressure": "inHg"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(body.units.temperature, TemperatureUnit::Fahrenheit);
+        assert_eq!(body.units.wind_speed, SpeedUnit::Knots);
+        assert_eq!(body.units.pressure, PressureUnit::InchesOfMercury);
+
+        let body: RequestBody =
+            serde_json::from_str(r#"{"city_query": "Madrid,ES", "units": "C"}"#).unwrap();
+
+        assert_eq!(
+            body.location,
+            LocationQuery::CityQuery {
+                city_query: "Madrid,ES".into()
+            }
+        );
     }
 }
@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FmtResult};
 
-use crate::models::request::{RequestType, TemperatureFormat};
+use crate::api_keys::KeyStats;
+use crate::models::{api::APIResponse, request::RequestType};
+use crate::providers::failover::ProviderStats;
+use crate::quota::QuotaStats;
+use crate::resilience::CircuitStatus;
 
 #[derive(Deserialize, Serialize)]
 pub struct City {
@@ -29,19 +34,179 @@ impl CityEntry {
     }
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
+pub struct ResolvedLocation {
+    pub cache_location: CacheLocation,
+    pub lat: f32,
+    pub lon: f32,
+}
+
+impl ResolvedLocation {
+    pub fn from_city(city_entry: CityEntry) -> Self {
+        ResolvedLocation {
+            cache_location: CacheLocation::City(city_entry.city_id),
+            lat: city_entry.city_lat,
+            lon: city_entry.city_lon,
+        }
+    }
+
+    // Nearby coordinates share the same cache entry, so the upstream
+    // is queried with the rounded coordinates that make up the key
+    pub fn from_coordinates(lat: f32, lon: f32) -> Option<Self> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return None;
+        }
+
+        let rounded_lat = (lat * CacheLocation::COORDINATE_SCALE).round() as i32;
+        let rounded_lon = (lon * CacheLocation::COORDINATE_SCALE).round() as i32;
+
+        Some(ResolvedLocation {
+            cache_location: CacheLocation::Coordinates(rounded_lat, rounded_lon),
+            lat: rounded_lat as f32 / CacheLocation::COORDINATE_SCALE,
+            lon: rounded_lon as f32 / CacheLocation::COORDINATE_SCALE,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Hash, Debug, Copy, Clone)]
+pub enum CacheLocation {
+    City(u32),
+    // Latitude and longitude scaled by COORDINATE_SCALE and rounded
+    Coordinates(i32, i32),
+}
+
+impl CacheLocation {
+    pub const COORDINATE_SCALE: f32 = 100.0; // 2 decimals, roughly 1km
+}
+
+impl Display for CacheLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            CacheLocation::City(city_id) => write!(f, "{}", city_id),
+            CacheLocation::Coordinates(lat, lon) => write!(
+                f,
+                "({},{})",
+                *lat as f32 / CacheLocation::COORDINATE_SCALE,
+                *lon as f32 / CacheLocation::COORDINATE_SCALE
+            ),
+        }
+    }
+}
+
+// Responses are cached in a single unit system, see units::CANONICAL_FORMAT. Keys
+// persisted with a unit system of their own are rejected, as they may hold other units
+#[derive(Deserialize, Serialize, Eq, PartialEq, Hash, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct CacheKey {
-    pub city_id: u32,
-    pub temperature_fmt: TemperatureFormat,
+    pub location: CacheLocation,
     pub req_type: RequestType,
 }
 
 impl CacheKey {
-    pub fn from(city_id: u32, temperature_fmt: TemperatureFormat, req_type: RequestType) -> Self {
-        CacheKey {
-            city_id,
-            temperature_fmt,
-            req_type,
+    pub fn from(location: CacheLocation, req_type: RequestType) -> Self {
+        CacheKey { location, req_type }
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub response: APIResponse,
+    // Past its soft TTL, it should be refreshed
+    pub stale: bool,
+    // Epoch seconds, None when the response was not cached or never expires
+    pub cached_at: Option<u64>,
+    pub expires_at: Option<u64>,
+}
+
+impl CachedResponse {
+    pub fn uncached(response: APIResponse) -> Self {
+        CachedResponse {
+            response,
+            stale: false,
+            cached_at: None,
+            expires_at: None,
         }
     }
 }
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct CacheStats {
+    // Left out by the backends that can't tell them, like the ones shared between replicas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<usize>,
+    // Approximate size of the cached responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<usize>,
+    pub hits: u64,
+    // Hits served stale while being refreshed, also counted in hits
+    pub stale_hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+#[derive(Serialize)]
+pub struct Metrics {
+    pub cache: CacheStats,
+    // Only reported by providers with a budget of calls and API keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub api_keys: Vec<KeyStats>,
+    // Responses served and failovers of each provider, only with a failover chain
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<ProviderStats>,
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    // The upstream is failing, only cached responses can be served
+    Degraded,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    // Name of the weather provider serving the data
+    pub provider: &'static str,
+    pub upstream: CircuitStatus,
+    // Providers used when the primary one fails, in the order they are tried
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<ProviderHealth>,
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct ProviderHealth {
+    pub provider: &'static str,
+    pub upstream: CircuitStatus,
+}
+
+#[cfg(test)]
+mod test_resolved_location {
+    use super::*;
+
+    #[test]
+    fn check_coordinate_rounding() {
+        let location = ResolvedLocation::from_coordinates(40.41678, -3.70379).unwrap();
+
+        assert_eq!(
+            location.cache_location,
+            CacheLocation::Coordinates(4042, -370)
+        );
+        assert!((location.lat - 40.42).abs() < f32::EPSILON);
+        assert!((location.lon + 3.70).abs() < f32::EPSILON);
+
+        let nearby_location = ResolvedLocation::from_coordinates(40.4201, -3.6984).unwrap();
+
+        assert_eq!(location.cache_location, nearby_location.cache_location);
+    }
+
+    #[test]
+    fn check_invalid_coordinates() {
+        assert!(ResolvedLocation::from_coordinates(91.0, 0.0).is_none());
+        assert!(ResolvedLocation::from_coordinates(0.0, -180.5).is_none());
+        assert!(ResolvedLocation::from_coordinates(f32::NAN, 0.0).is_none());
+    }
+}
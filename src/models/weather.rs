@@ -0,0 +1,100 @@
+use crate::models::api::{
+    AirQuality, DailyFeelsLike, DailyTemperature, WeatherAlert, WeatherCondition, WeatherMinutely,
+};
+
+// Weather data as retrieved by any provider, always in metric units. Every upstream maps
+// its own format into it, and the values it doesn't provide are left out, never estimated
+#[derive(Clone, Default)]
+pub struct WeatherReport {
+    pub lat: Option<f32>,
+    pub lon: Option<f32>,
+    pub timezone: Option<String>,
+    // Shift in seconds from UTC
+    pub timezone_offset: Option<i32>,
+    pub current: Option<CurrentWeather>,
+    pub minutely: Option<Vec<WeatherMinutely>>,
+    pub hourly: Option<Vec<HourlyWeather>>,
+    pub daily: Option<Vec<DailyWeather>>,
+    pub alerts: Option<Vec<WeatherAlert>>,
+    pub air_quality: Option<Vec<AirQuality>>,
+    // Only set by a failover chain, to the provider that served the report
+    pub provider: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct CurrentWeather {
+    pub dt: u32,
+    pub sunrise: Option<u32>,
+    pub sunset: Option<u32>,
+    pub temperature: f32,
+    pub feels_like: Option<f32>,
+    // In hPa
+    pub pressure: f32,
+    pub humidity: u32,
+    pub dew_point: Option<f32>,
+    pub uv_index: Option<f32>,
+    pub clouds: u32,
+    // In meters
+    pub visibility: Option<f32>,
+    // In m/s
+    pub wind_speed: f32,
+    pub wind_gust: Option<f32>,
+    pub wind_direction: u32,
+    // Precipitation in mm for the last hour
+    pub rain: Option<f32>,
+    pub snow: Option<f32>,
+    pub conditions: Vec<WeatherCondition>,
+}
+
+#[derive(Clone, Default)]
+pub struct HourlyWeather {
+    pub dt: u32,
+    pub temperature: f32,
+    pub feels_like: Option<f32>,
+    // In hPa
+    pub pressure: f32,
+    pub humidity: u32,
+    pub dew_point: Option<f32>,
+    pub uv_index: Option<f32>,
+    pub clouds: u32,
+    // In meters
+    pub visibility: Option<f32>,
+    // In m/s
+    pub wind_speed: f32,
+    pub wind_gust: Option<f32>,
+    pub wind_direction: u32,
+    // Precipitation in mm for the hour
+    pub rain: Option<f32>,
+    pub snow: Option<f32>,
+    pub conditions: Vec<WeatherCondition>,
+    // Probability of precipitation from 0 to 1, only forecasts have it
+    pub pop: Option<f32>,
+}
+
+#[derive(Clone)]
+pub struct DailyWeather {
+    pub dt: u32,
+    pub sunrise: Option<u32>,
+    pub sunset: Option<u32>,
+    pub moonrise: Option<u32>,
+    pub moonset: Option<u32>,
+    // 0 and 1 are new moon, 0.5 is full moon
+    pub moon_phase: Option<f32>,
+    pub temperature: DailyTemperature,
+    pub feels_like: Option<DailyFeelsLike>,
+    // In hPa
+    pub pressure: f32,
+    pub humidity: u32,
+    pub dew_point: Option<f32>,
+    pub uv_index: Option<f32>,
+    pub clouds: u32,
+    // In m/s
+    pub wind_speed: f32,
+    pub wind_gust: Option<f32>,
+    pub wind_direction: u32,
+    pub conditions: Vec<WeatherCondition>,
+    pub pop: Option<f32>,
+    // Precipitation in mm for the day
+    pub rain: Option<f32>,
+    pub snow: Option<f32>,
+}
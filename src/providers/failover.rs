@@ -0,0 +1,309 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::api_keys::KeyStats;
+use crate::error::ApiError;
+use crate::models::request::RequestType;
+use crate::models::state::ProviderHealth;
+use crate::models::weather::WeatherReport;
+use crate::providers::WeatherProvider;
+use crate::quota::QuotaStats;
+use crate::resilience::CircuitStatus;
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct ProviderStats {
+    pub provider: &'static str,
+    pub served: u64,
+    // Times it failed and the request was passed on to the next provider
+    pub failovers: u64,
+}
+
+struct ChainedProvider {
+    provider: Box<dyn WeatherProvider>,
+    served: AtomicU64,
+    failovers: AtomicU64,
+}
+
+// Tries each provider in order until one serves the request. Providers with an open
+// circuit fail right away, so requests go straight to the next one while it stays open
+pub struct FailoverProvider {
+    providers: Vec<ChainedProvider>,
+}
+
+impl FailoverProvider {
+    // The first provider is the primary one, so a chain without any can't be built
+    pub fn build(providers: Vec<Box<dyn WeatherProvider>>) -> Option<Self> {
+        if providers.is_empty() {
+            return None;
+        }
+
+        Some(FailoverProvider {
+            providers: providers
+                .into_iter()
+                .map(|provider| ChainedProvider {
+                    provider,
+                    served: AtomicU64::new(0),
+                    failovers: AtomicU64::new(0),
+                })
+                .collect(),
+        })
+    }
+
+    fn primary(&self) -> &dyn WeatherProvider {
+        self.providers[0].provider.as_ref()
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for FailoverProvider {
+    // Named after the primary provider, the one expected to serve the requests
+    fn name(&self) -> &'static str {
+        self.primary().name()
+    }
+
+    fn supports(&self, request_type: RequestType) -> bool {
+        self.providers
+            .iter()
+            .any(|chained| chained.provider.supports(request_type))
+    }
+
+    async fn fetch(
+        &self,
+        request_type: RequestType,
+        lat: f32,
+        lon: f32,
+    ) -> Result<WeatherReport, ApiError> {
+        let candidates = self
+            .providers
+            .iter()
+            .filter(|chained| chained.provider.supports(request_type))
+            .collect::<Vec<&ChainedProvider>>();
+
+        let mut last_error = None;
+
+        for (idx, chained) in candidates.iter().enumerate() {
+            let name = chained.provider.name();
+
+            match chained.provider.fetch(request_type, lat, lon).await {
+                Ok(mut response) => {
+                    chained.served.fetch_add(1, Ordering::Relaxed);
+                    response.provider = Some(name.to_owned());
+
+                    return Ok(response);
+                }
+                // Errors of the request itself would be the same with any provider
+                Err(err) if !err.is_upstream_failure() => return Err(err),
+                Err(err) => {
+                    if let Some(next) = candidates.get(idx + 1) {
+                        chained.failovers.fetch_add(1, Ordering::Relaxed);
+
+                        log::warn!(
+                            "{} failed to serve {} data, failing over to {} - {}",
+                            name,
+                            request_type.name(),
+                            next.provider.name(),
+                            err
+                        );
+                    }
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ApiError::Unsupported(format!(
+                "No weather provider has {} data",
+                request_type.name()
+            ))
+        }))
+    }
+
+    fn circuit_status(&self) -> CircuitStatus {
+        self.primary().circuit_status()
+    }
+
+    fn quota_stats(&self) -> Option<QuotaStats> {
+        self.providers
+            .iter()
+            .find_map(|chained| chained.provider.quota_stats())
+    }
+
+    fn key_stats(&self) -> Vec<KeyStats> {
+        self.providers
+            .iter()
+            .flat_map(|chained| chained.provider.key_stats())
+            .collect()
+    }
+
+    fn fallbacks(&self) -> Vec<ProviderHealth> {
+        self.providers
+            .iter()
+            .skip(1)
+            .map(|chained| ProviderHealth {
+                provider: chained.provider.name(),
+                upstream: chained.provider.circuit_status(),
+            })
+            .collect()
+    }
+
+    fn provider_stats(&self) -> Vec<ProviderStats> {
+        self.providers
+            .iter()
+            .map(|chained| ProviderStats {
+                provider: chained.provider.name(),
+                served: chained.served.load(Ordering::Relaxed),
+                failovers: chained.failovers.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_failover {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use crate::resilience::CircuitState;
+
+    // Replies every request the same way, counting the calls
+    struct StubProvider {
+        name: &'static str,
+        result: Result<WeatherReport, ApiError>,
+        supports_air: bool,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl StubProvider {
+        fn build(
+            name: &'static str,
+            result: Result<WeatherReport, ApiError>,
+            supports_air: bool,
+        ) -> (Box<dyn WeatherProvider>, Arc<AtomicUsize>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+
+            let provider = StubProvider {
+                name,
+                result,
+                supports_air,
+                calls: calls.clone(),
+            };
+
+            (Box::new(provider), calls)
+        }
+    }
+
+    #[async_trait]
+    impl WeatherProvider for StubProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn supports(&self, request_type: RequestType) -> bool {
+            self.supports_air || request_type != RequestType::AirQuality
+        }
+
+        async fn fetch(&self, _: RequestType, _: f32, _: f32) -> Result<WeatherReport, ApiError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.result.clone()
+        }
+
+        fn circuit_status(&self) -> CircuitStatus {
+            CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn check_failover() {
+        let (primary, primary_calls) = StubProvider::build(
+            "primary",
+            Err(ApiError::UpstreamTimeout("Timed out".into())),
+            true,
+        );
+        let (secondary, secondary_calls) =
+            StubProvider::build("secondary", Ok(WeatherReport::default()), false);
+
+        let provider = FailoverProvider::build(vec![primary, secondary]).unwrap();
+
+        let response = provider
+            .fetch(RequestType::CurrentWeather, 40.42, -3.7)
+            .await
+            .unwrap();
+
+        assert_eq!(response.provider.as_deref(), Some("secondary"));
+        assert_eq!(primary_calls.load(Ordering::Relaxed), 1);
+        assert_eq!(secondary_calls.load(Ordering::Relaxed), 1);
+
+        // Only the primary has air quality data, so there is nothing to fail over to
+        let error = provider
+            .fetch(RequestType::AirQuality, 40.42, -3.7)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error, ApiError::UpstreamTimeout("Timed out".into()));
+        assert_eq!(secondary_calls.load(Ordering::Relaxed), 1);
+
+        assert_eq!(provider.name(), "primary");
+        assert_eq!(
+            provider.provider_stats(),
+            vec![
+                ProviderStats {
+                    provider: "primary",
+                    served: 0,
+                    failovers: 1,
+                },
+                ProviderStats {
+                    provider: "secondary",
+                    served: 1,
+                    failovers: 0,
+                },
+            ]
+        );
+        assert_eq!(provider.fallbacks()[0].provider, "secondary");
+    }
+
+    #[actix_rt::test]
+    async fn check_request_errors() {
+        let (primary, _) = StubProvider::build(
+            "primary",
+            Err(ApiError::LocationNotFound("Unknown location".into())),
+            false,
+        );
+        let (secondary, secondary_calls) =
+            StubProvider::build("secondary", Ok(WeatherReport::default()), false);
+
+        let provider = FailoverProvider::build(vec![primary, secondary]).unwrap();
+
+        let error = provider
+            .fetch(RequestType::DailyForecast, 40.42, -3.7)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "location_not_found");
+        assert_eq!(secondary_calls.load(Ordering::Relaxed), 0);
+
+        assert!(!provider.supports(RequestType::AirQuality));
+        assert_eq!(
+            provider
+                .fetch(RequestType::AirQuality, 40.42, -3.7)
+                .await
+                .err()
+                .unwrap()
+                .code(),
+            "unsupported_request"
+        );
+    }
+
+    #[test]
+    fn check_empty_chain() {
+        assert!(FailoverProvider::build(vec![]).is_none());
+    }
+}
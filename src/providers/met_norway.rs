@@ -0,0 +1,648 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::models::api::{DailyTemperature, WeatherCondition};
+use crate::models::request::{self, RequestType};
+use crate::models::weather::{CurrentWeather, DailyWeather, HourlyWeather, WeatherReport};
+use crate::providers::{self, ProviderConfig, WeatherProvider};
+use crate::resilience::{CircuitStatus, UpstreamGuard};
+
+// GeoJSON feature sent by the locationforecast API
+#[derive(Deserialize)]
+struct Locationforecast {
+    geometry: Geometry,
+    properties: ForecastProperties,
+}
+
+#[derive(Deserialize)]
+struct Geometry {
+    // Longitude, latitude and altitude
+    coordinates: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct ForecastProperties {
+    timeseries: Vec<ForecastStep>,
+}
+
+// Hourly steps for the first days, then every six hours
+#[derive(Deserialize)]
+struct ForecastStep {
+    time: String,
+    data: StepData,
+}
+
+#[derive(Deserialize)]
+struct StepData {
+    instant: InstantData,
+    next_1_hours: Option<PeriodData>,
+    next_6_hours: Option<PeriodData>,
+    next_12_hours: Option<PeriodData>,
+}
+
+#[derive(Deserialize)]
+struct InstantData {
+    details: InstantDetails,
+}
+
+#[derive(Deserialize)]
+struct InstantDetails {
+    air_temperature: f32,
+    air_pressure_at_sea_level: f32,
+    relative_humidity: f32,
+    dew_point_temperature: Option<f32>,
+    cloud_area_fraction: f32,
+    wind_speed: f32,
+    wind_from_direction: f32,
+    wind_speed_of_gust: Option<f32>,
+    ultraviolet_index_clear_sky: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct PeriodData {
+    summary: Option<PeriodSummary>,
+    #[serde(default)]
+    details: PeriodDetails,
+}
+
+#[derive(Deserialize)]
+struct PeriodSummary {
+    symbol_code: String,
+}
+
+#[derive(Deserialize, Default)]
+struct PeriodDetails {
+    precipitation_amount: Option<f32>,
+    probability_of_precipitation: Option<f32>,
+}
+
+impl ForecastStep {
+    // Times are always sent in UTC, e.g. 2020-11-02T12:00:00Z
+    fn epoch(&self) -> Option<u32> {
+        let (date, time) = self.time.trim_end_matches('Z').split_once('T')?;
+
+        let seconds = time
+            .split(':')
+            .map(|part| part.parse::<u32>().ok())
+            .collect::<Option<Vec<u32>>>()?;
+
+        match seconds.as_slice() {
+            [hours, minutes, seconds] => {
+                Some(request::parse_date(date)? * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+            }
+            _ => None,
+        }
+    }
+
+    // Shortest period with a summary, it's the most accurate one
+    fn period(&self) -> Option<&PeriodData> {
+        [
+            &self.data.next_1_hours,
+            &self.data.next_6_hours,
+            &self.data.next_12_hours,
+        ]
+        .iter()
+        .find_map(|period| period.as_ref().filter(|period| period.summary.is_some()))
+    }
+
+    fn condition(&self) -> Option<WeatherCondition> {
+        self.period()
+            .and_then(|period| period.summary.as_ref())
+            .map(|summary| MetNorwayProvider::condition(&summary.symbol_code))
+    }
+}
+
+// Precipitation is reported as a single amount, the condition tells if it's snow
+fn is_snow(condition: &Option<WeatherCondition>) -> bool {
+    condition
+        .as_ref()
+        .is_some_and(|condition| (600..700).contains(&condition.id))
+}
+
+fn precipitation(
+    amount: Option<f32>,
+    condition: &Option<WeatherCondition>,
+) -> (Option<f32>, Option<f32>) {
+    match amount {
+        Some(amount) if is_snow(condition) => (None, Some(amount)),
+        Some(amount) => (Some(amount), None),
+        None => (None, None),
+    }
+}
+
+pub struct MetNorwayProvider {
+    client: reqwest::Client,
+    guard: UpstreamGuard,
+    forecast_url: String,
+}
+
+impl MetNorwayProvider {
+    pub const NAME: &'static str = "MET Norway";
+
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.met.no";
+
+    const FORECAST_HOURS: usize = 48;
+
+    const FORECAST_DAYS: usize = 8;
+
+    pub fn build(config: ProviderConfig) -> Self {
+        MetNorwayProvider {
+            client: config.resilience.build_http_client(),
+            forecast_url: format!(
+                "{}/weatherapi/locationforecast/2.0/complete",
+                config.base_url.trim_end_matches('/')
+            ),
+            guard: UpstreamGuard::build(MetNorwayProvider::NAME, config.resilience),
+        }
+    }
+
+    async fn query_forecast(&self, lat: f32, lon: f32) -> Result<Locationforecast, ApiError> {
+        // The terms of service ask for no more than four decimals, so responses can be cached
+        let query_params = &[
+            ("lat", format!("{:.4}", lat)),
+            ("lon", format!("{:.4}", lon)),
+        ];
+        let (client, forecast_url) = (&self.client, &self.forecast_url);
+
+        self.guard
+            .call(move || async move {
+                client
+                    .get(forecast_url)
+                    .query(query_params)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Locationforecast>()
+                    .await
+            })
+            .await
+    }
+
+    fn build_report(forecast: &Locationforecast) -> WeatherReport {
+        WeatherReport {
+            lat: forecast.geometry.coordinates.get(1).copied(),
+            lon: forecast.geometry.coordinates.first().copied(),
+            ..Default::default()
+        }
+    }
+
+    // Steps whose time can't be read are left out
+    fn timed_steps(forecast: &Locationforecast) -> impl Iterator<Item = (u32, &ForecastStep)> {
+        forecast
+            .properties
+            .timeseries
+            .iter()
+            .filter_map(|step| Some((step.epoch()?, step)))
+    }
+
+    // There is no apparent temperature nor times of the sun in the forecast
+    fn build_current(dt: u32, step: &ForecastStep) -> CurrentWeather {
+        let details = &step.data.instant.details;
+        let condition = step.condition();
+        let (rain, snow) = precipitation(
+            step.data
+                .next_1_hours
+                .as_ref()
+                .and_then(|period| period.details.precipitation_amount),
+            &condition,
+        );
+
+        CurrentWeather {
+            dt,
+            sunrise: None,
+            sunset: None,
+            temperature: details.air_temperature,
+            feels_like: None,
+            pressure: details.air_pressure_at_sea_level,
+            humidity: details.relative_humidity.round() as u32,
+            dew_point: details.dew_point_temperature,
+            uv_index: details.ultraviolet_index_clear_sky,
+            clouds: details.cloud_area_fraction.round() as u32,
+            visibility: None,
+            wind_speed: details.wind_speed,
+            wind_gust: details.wind_speed_of_gust,
+            wind_direction: details.wind_from_direction.round() as u32,
+            rain,
+            snow,
+            conditions: condition.into_iter().collect(),
+        }
+    }
+
+    fn build_hourly(forecast: &Locationforecast) -> Vec<HourlyWeather> {
+        MetNorwayProvider::timed_steps(forecast)
+            .filter(|(_, step)| step.data.next_1_hours.is_some())
+            .take(MetNorwayProvider::FORECAST_HOURS)
+            .map(|(dt, step)| {
+                let current = MetNorwayProvider::build_current(dt, step);
+
+                HourlyWeather {
+                    dt,
+                    temperature: current.temperature,
+                    feels_like: current.feels_like,
+                    pressure: current.pressure,
+                    humidity: current.humidity,
+                    dew_point: current.dew_point,
+                    uv_index: current.uv_index,
+                    clouds: current.clouds,
+                    visibility: current.visibility,
+                    wind_speed: current.wind_speed,
+                    wind_gust: current.wind_gust,
+                    wind_direction: current.wind_direction,
+                    rain: current.rain,
+                    snow: current.snow,
+                    conditions: current.conditions,
+                    pop: step
+                        .data
+                        .next_1_hours
+                        .as_ref()
+                        .and_then(|period| period.details.probability_of_precipitation)
+                        .map(|pop| pop / 100.0),
+                }
+            })
+            .collect()
+    }
+
+    // Days are split by solar time, the response doesn't include the timezone of the location
+    fn build_daily(forecast: &Locationforecast, lon: f32) -> Vec<DailyWeather> {
+        let offset = (lon / 15.0).round() as i64 * 3_600;
+        let local_day = |dt: u32| (i64::from(dt) + offset).div_euclid(86_400);
+
+        let steps = MetNorwayProvider::timed_steps(forecast).collect::<Vec<(u32, &ForecastStep)>>();
+
+        let mut days: Vec<(i64, Vec<(u32, &ForecastStep)>)> = vec![];
+        for (dt, step) in steps {
+            match days.last_mut() {
+                Some((day, day_steps)) if *day == local_day(dt) => day_steps.push((dt, step)),
+                _ => days.push((local_day(dt), vec![(dt, step)])),
+            }
+        }
+
+        days.into_iter()
+            .take(MetNorwayProvider::FORECAST_DAYS)
+            .filter_map(|(day, day_steps)| {
+                let day_start = (day * 86_400 - offset) as u32;
+                fn details<'a>((_, step): &(u32, &'a ForecastStep)) -> &'a InstantDetails {
+                    &step.data.instant.details
+                }
+
+                // Closest step at or after the given hour of the day, or the last one
+                let at_hour = |hour: u32| {
+                    day_steps
+                        .iter()
+                        .find(|(dt, _)| *dt >= day_start + hour * 3_600)
+                        .or_else(|| day_steps.last())
+                        .unwrap()
+                };
+                let temp_at = |hour: u32| details(at_hour(hour)).air_temperature;
+                let steps_mean = |value: fn(&InstantDetails) -> Option<f32>| {
+                    providers::mean(day_steps.iter().filter_map(|step| value(details(step))))
+                };
+
+                // Hourly steps carry the precipitation of their hour, the later ones of six hours
+                let precipitation = day_steps
+                    .iter()
+                    .filter_map(|(_, step)| match &step.data.next_1_hours {
+                        Some(period) => period.details.precipitation_amount,
+                        None => step
+                            .data
+                            .next_6_hours
+                            .as_ref()
+                            .and_then(|period| period.details.precipitation_amount),
+                    })
+                    .sum::<f32>();
+                let pop = providers::max(day_steps.iter().filter_map(|(_, step)| {
+                    step.period()
+                        .and_then(|period| period.details.probability_of_precipitation)
+                }));
+
+                let midday = at_hour(12);
+                let condition = midday.1.condition();
+
+                Some(DailyWeather {
+                    dt: day_start,
+                    sunrise: None,
+                    sunset: None,
+                    moonrise: None,
+                    moonset: None,
+                    moon_phase: None,
+                    temperature: DailyTemperature {
+                        min: providers::min(
+                            day_steps.iter().map(|step| details(step).air_temperature),
+                        )?,
+                        max: providers::max(
+                            day_steps.iter().map(|step| details(step).air_temperature),
+                        )?,
+                        morn: temp_at(6),
+                        day: temp_at(12),
+                        eve: temp_at(18),
+                        night: temp_at(0),
+                    },
+                    feels_like: None,
+                    pressure: steps_mean(|details| Some(details.air_pressure_at_sea_level))?,
+                    humidity: steps_mean(|details| Some(details.relative_humidity))?.round() as u32,
+                    dew_point: steps_mean(|details| details.dew_point_temperature),
+                    uv_index: providers::max(
+                        day_steps
+                            .iter()
+                            .filter_map(|step| details(step).ultraviolet_index_clear_sky),
+                    ),
+                    clouds: steps_mean(|details| Some(details.cloud_area_fraction))?.round() as u32,
+                    wind_speed: providers::max(
+                        day_steps.iter().map(|step| details(step).wind_speed),
+                    )?,
+                    wind_gust: providers::max(
+                        day_steps
+                            .iter()
+                            .filter_map(|step| details(step).wind_speed_of_gust),
+                    ),
+                    wind_direction: details(midday).wind_from_direction.round() as u32,
+                    pop: pop.map(|pop| pop / 100.0),
+                    rain: Some(precipitation).filter(|_| !is_snow(&condition)),
+                    snow: Some(precipitation).filter(|_| is_snow(&condition)),
+                    conditions: condition.into_iter().collect(),
+                })
+            })
+            .collect()
+    }
+
+    // Symbol codes, e.g. lightrainshowers_day, to their OpenWeatherMap equivalent
+    fn condition(symbol_code: &str) -> WeatherCondition {
+        let mut parts = symbol_code.splitn(2, '_');
+        let symbol = parts.next().unwrap_or_default();
+        let is_day = parts.next() != Some("night");
+
+        let intensity = |light: u32, moderate: u32, heavy: u32| {
+            if symbol.starts_with("light") {
+                light
+            } else if symbol.starts_with("heavy") {
+                heavy
+            } else {
+                moderate
+            }
+        };
+
+        let id = if symbol.contains("thunder") {
+            intensity(200, 201, 202)
+        } else if symbol.contains("sleet") {
+            611
+        } else if symbol.contains("snowshowers") {
+            intensity(620, 621, 622)
+        } else if symbol.contains("snow") {
+            intensity(600, 601, 602)
+        } else if symbol.contains("rainshowers") {
+            intensity(520, 521, 522)
+        } else if symbol.contains("rain") {
+            intensity(500, 501, 502)
+        } else {
+            match symbol {
+                "fog" => 741,
+                "fair" => 801,
+                "partlycloudy" => 802,
+                "cloudy" => 804,
+                _ => 800,
+            }
+        };
+
+        providers::weather_condition(id, is_day)
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for MetNorwayProvider {
+    fn name(&self) -> &'static str {
+        MetNorwayProvider::NAME
+    }
+
+    fn supports(&self, request_type: RequestType) -> bool {
+        matches!(
+            request_type,
+            RequestType::CurrentWeather | RequestType::WeatherForecast | RequestType::DailyForecast
+        )
+    }
+
+    async fn fetch(
+        &self,
+        request_type: RequestType,
+        lat: f32,
+        lon: f32,
+    ) -> Result<WeatherReport, ApiError> {
+        if !self.supports(request_type) {
+            return Err(ApiError::Unsupported(format!(
+                "MET Norway has no {} data",
+                request_type.name()
+            )));
+        }
+
+        let forecast = self.query_forecast(lat, lon).await?;
+        let response = MetNorwayProvider::build_report(&forecast);
+
+        Ok(match request_type {
+            RequestType::CurrentWeather => {
+                let (dt, step) = MetNorwayProvider::timed_steps(&forecast)
+                    .next()
+                    .ok_or_else(|| {
+                        ApiError::UpstreamError("MET Norway sent an empty forecast".into())
+                    })?;
+
+                WeatherReport {
+                    current: Some(MetNorwayProvider::build_current(dt, step)),
+                    ..response
+                }
+            }
+            RequestType::WeatherForecast => WeatherReport {
+                hourly: Some(MetNorwayProvider::build_hourly(&forecast)),
+                ..response
+            },
+            _ => WeatherReport {
+                daily: Some(MetNorwayProvider::build_daily(
+                    &forecast,
+                    response.lon.unwrap_or(lon),
+                )),
+                ..response
+            },
+        })
+    }
+
+    fn circuit_status(&self) -> CircuitStatus {
+        self.guard.status()
+    }
+}
+
+#[cfg(test)]
+mod test_met_norway {
+    use super::*;
+
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+
+    use crate::resilience::ResilienceConfig;
+
+    async fn locationforecast_fixture(req: HttpRequest) -> HttpResponse {
+        // Requests without an identifying user agent are rejected by the upstream
+        if req.headers().get("user-agent").is_none() {
+            return HttpResponse::Forbidden().finish();
+        }
+
+        if req.query_string() != "lat=59.9100&lon=10.7500" {
+            return HttpResponse::BadRequest().finish();
+        }
+
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!(
+                "../../tests/fixtures/met_norway_complete.json"
+            ))
+    }
+
+    fn mock_provider() -> (test::TestServer, MetNorwayProvider) {
+        let srv = test::start(|| {
+            App::new().route(
+                "/weatherapi/locationforecast/2.0/complete",
+                web::get().to(locationforecast_fixture),
+            )
+        });
+
+        let provider = MetNorwayProvider::build(ProviderConfig {
+            base_url: srv.url("/"),
+            resilience: ResilienceConfig::default(),
+        });
+
+        (srv, provider)
+    }
+
+    // Symbols of the Locationforecast API, without the day or night suffix, with the
+    // misspellings of the published list
+    const SYMBOLS: [&str; 41] = [
+        "clearsky",
+        "cloudy",
+        "fair",
+        "fog",
+        "heavyrain",
+        "heavyrainandthunder",
+        "heavyrainshowers",
+        "heavyrainshowersandthunder",
+        "heavysleet",
+        "heavysleetandthunder",
+        "heavysleetshowers",
+        "heavysleetshowersandthunder",
+        "heavysnow",
+        "heavysnowandthunder",
+        "heavysnowshowers",
+        "heavysnowshowersandthunder",
+        "lightrain",
+        "lightrainandthunder",
+        "lightrainshowers",
+        "lightrainshowersandthunder",
+        "lightsleet",
+        "lightsleetandthunder",
+        "lightsleetshowers",
+        "lightssleetshowersandthunder",
+        "lightsnow",
+        "lightsnowandthunder",
+        "lightsnowshowers",
+        "lightssnowshowersandthunder",
+        "partlycloudy",
+        "rain",
+        "rainandthunder",
+        "rainshowers",
+        "rainshowersandthunder",
+        "sleet",
+        "sleetandthunder",
+        "sleetshowers",
+        "sleetshowersandthunder",
+        "snow",
+        "snowandthunder",
+        "snowshowers",
+        "snowshowersandthunder",
+    ];
+
+    #[test]
+    fn check_symbol_codes() {
+        let condition = MetNorwayProvider::condition("lightrainshowers_day");
+
+        assert_eq!(condition.id, 520);
+        assert_eq!(condition.icon, "09d");
+
+        assert_eq!(MetNorwayProvider::condition("clearsky_night").icon, "01n");
+        assert_eq!(MetNorwayProvider::condition("partlycloudy_day").id, 802);
+        assert_eq!(MetNorwayProvider::condition("cloudy").id, 804);
+        assert_eq!(MetNorwayProvider::condition("heavysnow").id, 602);
+        assert_eq!(MetNorwayProvider::condition("sleetshowers_day").id, 611);
+        assert_eq!(MetNorwayProvider::condition("rainandthunder").id, 201);
+        assert_eq!(MetNorwayProvider::condition("fog").id, 741);
+
+        // Every symbol MET Norway publishes maps to a condition with a description of its own
+        for symbol in SYMBOLS {
+            let id = MetNorwayProvider::condition(symbol).id;
+
+            assert!(providers::condition_description(id).is_some(), "{}", symbol);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn check_locationforecast() {
+        let (_srv, provider) = mock_provider();
+
+        let response = provider
+            .fetch(RequestType::CurrentWeather, 59.91, 10.75)
+            .await
+            .unwrap();
+
+        assert_eq!(response.lat, Some(59.91));
+        assert_eq!(response.lon, Some(10.75));
+
+        let current = response.current.unwrap();
+
+        // 2026-10-16T12:00:00Z
+        assert_eq!(current.dt, 1_792_152_000);
+        assert_eq!(current.temperature, 8.4);
+        assert_eq!(current.humidity, 70);
+        assert_eq!(current.dew_point, Some(3.3));
+        assert_eq!(current.wind_direction, 199);
+        assert_eq!(current.conditions[0].id, 804);
+        // Not part of the forecast, so they're left out rather than estimated
+        assert!(current.feels_like.is_none());
+        assert!(current.sunrise.is_none());
+
+        let response = provider
+            .fetch(RequestType::WeatherForecast, 59.91, 10.75)
+            .await
+            .unwrap();
+        let hourly = response.hourly.unwrap();
+
+        assert_eq!(hourly.len(), 48);
+        assert!(hourly
+            .windows(2)
+            .all(|hours| hours[1].dt - hours[0].dt == 3_600));
+        assert!(hourly.iter().any(|hour| hour.rain > Some(0.0)));
+        assert!(hourly.iter().any(|hour| hour.snow > Some(0.0)));
+
+        let response = provider
+            .fetch(RequestType::DailyForecast, 59.91, 10.75)
+            .await
+            .unwrap();
+        let daily = response.daily.unwrap();
+
+        assert_eq!(daily.len(), 8);
+        // Days start at local midnight, an hour ahead of UTC
+        assert_eq!(daily[1].dt, 1_792_191_600);
+        assert!(daily
+            .iter()
+            .all(|day| day.temperature.min <= day.temperature.day));
+        assert!(daily
+            .iter()
+            .all(|day| day.temperature.day <= day.temperature.max));
+        assert!(daily
+            .iter()
+            .all(|day| day.pop.is_some_and(|pop| (0.0..=1.0).contains(&pop))));
+        assert!(daily.iter().all(|day| day.feels_like.is_none()));
+
+        let error = provider
+            .fetch(RequestType::Historical(18_568), 59.91, 10.75)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "unsupported_request");
+    }
+}
@@ -0,0 +1,207 @@
+pub mod failover;
+pub mod met_norway;
+pub mod open_meteo;
+
+use async_trait::async_trait;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use crate::api_keys::KeyStats;
+use crate::error::ApiError;
+use crate::models::api::WeatherCondition;
+use crate::models::request::RequestType;
+use crate::models::state::ProviderHealth;
+use crate::models::weather::WeatherReport;
+use crate::providers::failover::ProviderStats;
+use crate::quota::QuotaStats;
+use crate::resilience::{CircuitStatus, ResilienceConfig};
+
+// Source of the weather data. Whatever the format of its upstream, every provider maps
+// it into the same WeatherReport model in metric units, served in the OpenWeatherMap one
+#[async_trait]
+pub trait WeatherProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Not every upstream has data for every request type, e.g. alerts or air quality
+    fn supports(&self, request_type: RequestType) -> bool;
+
+    async fn fetch(
+        &self,
+        request_type: RequestType,
+        lat: f32,
+        lon: f32,
+    ) -> Result<WeatherReport, ApiError>;
+
+    fn circuit_status(&self) -> CircuitStatus;
+
+    // Only reported by the providers with a budget of calls
+    fn quota_stats(&self) -> Option<QuotaStats> {
+        None
+    }
+
+    // Only reported by the providers with API keys
+    fn key_stats(&self) -> Vec<KeyStats> {
+        vec![]
+    }
+
+    // Only reported by a failover chain, for the providers after the first one
+    fn fallbacks(&self) -> Vec<ProviderHealth> {
+        vec![]
+    }
+
+    fn provider_stats(&self) -> Vec<ProviderStats> {
+        vec![]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProviderKind {
+    OpenWeatherMap,
+    OpenMeteo,
+    MetNorway,
+}
+
+impl Display for ProviderKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ProviderKind::OpenWeatherMap => write!(f, "openweathermap"),
+            ProviderKind::OpenMeteo => write!(f, "open_meteo"),
+            ProviderKind::MetNorway => write!(f, "met_norway"),
+        }
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "openweathermap" => Ok(ProviderKind::OpenWeatherMap),
+            "open_meteo" => Ok(ProviderKind::OpenMeteo),
+            "met_norway" => Ok(ProviderKind::MetNorway),
+            other => Err(format!("Unsupported weather provider {}", other)),
+        }
+    }
+}
+
+// Configuration of the providers that don't need any key
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProviderConfig {
+    // Scheme and host of the upstream, without any path
+    pub base_url: String,
+    pub resilience: ResilienceConfig,
+}
+
+// Conditions are always described with the OpenWeatherMap codes and icons, which clients already know
+pub fn weather_condition(id: u32, is_day: bool) -> WeatherCondition {
+    let (condition, icon) = match id {
+        200..=299 => ("Thunderstorm", "11"),
+        300..=399 => ("Drizzle", "09"),
+        511 => ("Rain", "13"),
+        520..=531 => ("Rain", "09"),
+        500..=599 => ("Rain", "10"),
+        600..=699 => ("Snow", "13"),
+        700..=799 => ("Fog", "50"),
+        801 => ("Clouds", "02"),
+        802 => ("Clouds", "03"),
+        803 | 804 => ("Clouds", "04"),
+        _ => ("Clear", "01"),
+    };
+
+    WeatherCondition {
+        id,
+        condition: condition.into(),
+        // Ids without a description of their own are described by their condition
+        description: condition_description(id)
+            .map(str::to_owned)
+            .unwrap_or_else(|| condition.to_lowercase()),
+        icon: format!("{}{}", icon, if is_day { "d" } else { "n" }),
+    }
+}
+
+// Descriptions OpenWeatherMap gives to the ids the providers map their conditions to
+fn condition_description(id: u32) -> Option<&'static str> {
+    let description = match id {
+        200 => "thunderstorm with light rain",
+        201 => "thunderstorm with rain",
+        202 => "thunderstorm with heavy rain",
+        211 => "thunderstorm",
+        300 => "light intensity drizzle",
+        301 => "drizzle",
+        302 => "heavy intensity drizzle",
+        500 => "light rain",
+        501 => "moderate rain",
+        502 => "heavy intensity rain",
+        511 => "freezing rain",
+        520 => "light intensity shower rain",
+        521 => "shower rain",
+        522 => "heavy intensity shower rain",
+        600 => "light snow",
+        601 => "snow",
+        602 => "heavy snow",
+        611 => "sleet",
+        620 => "light shower snow",
+        621 => "shower snow",
+        622 => "heavy shower snow",
+        741 => "fog",
+        800 => "clear sky",
+        801 => "few clouds",
+        802 => "scattered clouds",
+        803 => "broken clouds",
+        804 => "overcast clouds",
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+// Mean of the values present, None if there are none
+fn mean(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+
+    if count > 0 {
+        Some(sum / count as f32)
+    } else {
+        None
+    }
+}
+
+fn max(values: impl Iterator<Item = f32>) -> Option<f32> {
+    values.fold(None, |max: Option<f32>, value| {
+        Some(max.map_or(value, |max| max.max(value)))
+    })
+}
+
+fn min(values: impl Iterator<Item = f32>) -> Option<f32> {
+    values.fold(None, |min: Option<f32>, value| {
+        Some(min.map_or(value, |min| min.min(value)))
+    })
+}
+
+#[cfg(test)]
+mod test_providers {
+    use super::*;
+
+    #[test]
+    fn check_weather_conditions() {
+        let condition = weather_condition(521, true);
+
+        assert_eq!(condition.condition, "Rain");
+        assert_eq!(condition.description, "shower rain");
+        assert_eq!(condition.icon, "09d");
+
+        assert_eq!(weather_condition(800, false).icon, "01n");
+        assert_eq!(weather_condition(741, true).condition, "Fog");
+        assert_eq!(weather_condition(804, true).description, "overcast clouds");
+        assert_eq!(weather_condition(211, true).description, "thunderstorm");
+        assert_eq!(weather_condition(232, true).description, "thunderstorm");
+
+        assert_eq!("met_norway".parse(), Ok(ProviderKind::MetNorway));
+        assert!("accuweather".parse::<ProviderKind>().is_err());
+        assert_eq!(ProviderKind::OpenMeteo.to_string(), "open_meteo");
+
+        assert_eq!(mean(vec![1.0, 2.0, 6.0].into_iter()), Some(3.0));
+        assert_eq!(max(vec![1.0, 7.5, 6.0].into_iter()), Some(7.5));
+        assert_eq!(min(std::iter::empty()), None);
+    }
+}
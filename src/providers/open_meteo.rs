@@ -0,0 +1,737 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::models::api::{DailyFeelsLike, DailyTemperature};
+use crate::models::request::{self, RequestType};
+use crate::models::weather::{CurrentWeather, DailyWeather, HourlyWeather, WeatherReport};
+use crate::providers::{self, ProviderConfig, WeatherProvider};
+use crate::resilience::{CircuitStatus, UpstreamGuard};
+use crate::utils;
+
+#[derive(Deserialize)]
+struct ForecastResponse {
+    latitude: f32,
+    longitude: f32,
+    timezone: Option<String>,
+    utc_offset_seconds: Option<i32>,
+    current: Option<CurrentData>,
+    hourly: Option<HourlyData>,
+    daily: Option<DailyData>,
+}
+
+#[derive(Deserialize)]
+struct CurrentData {
+    time: u32,
+    temperature_2m: f32,
+    apparent_temperature: f32,
+    relative_humidity_2m: f32,
+    dew_point_2m: f32,
+    pressure_msl: f32,
+    cloud_cover: f32,
+    visibility: Option<f32>,
+    wind_speed_10m: f32,
+    wind_direction_10m: f32,
+    wind_gusts_10m: Option<f32>,
+    uv_index: Option<f32>,
+    rain: Option<f32>,
+    snowfall: Option<f32>,
+    weather_code: u32,
+    is_day: u8,
+}
+
+// Values are sent as one array per variable, with nulls where the model has no data
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct HourlyData {
+    time: Vec<u32>,
+    temperature_2m: Vec<Option<f32>>,
+    apparent_temperature: Vec<Option<f32>>,
+    relative_humidity_2m: Vec<Option<f32>>,
+    dew_point_2m: Vec<Option<f32>>,
+    pressure_msl: Vec<Option<f32>>,
+    cloud_cover: Vec<Option<f32>>,
+    visibility: Vec<Option<f32>>,
+    wind_speed_10m: Vec<Option<f32>>,
+    wind_direction_10m: Vec<Option<f32>>,
+    wind_gusts_10m: Vec<Option<f32>>,
+    uv_index: Vec<Option<f32>>,
+    rain: Vec<Option<f32>>,
+    snowfall: Vec<Option<f32>>,
+    precipitation_probability: Vec<Option<f32>>,
+    weather_code: Vec<Option<u32>>,
+    is_day: Vec<Option<u8>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DailyData {
+    time: Vec<u32>,
+    sunrise: Vec<Option<u32>>,
+    sunset: Vec<Option<u32>>,
+    temperature_2m_max: Vec<Option<f32>>,
+    temperature_2m_min: Vec<Option<f32>>,
+    uv_index_max: Vec<Option<f32>>,
+    wind_speed_10m_max: Vec<Option<f32>>,
+    wind_gusts_10m_max: Vec<Option<f32>>,
+    wind_direction_10m_dominant: Vec<Option<f32>>,
+    precipitation_probability_max: Vec<Option<f32>>,
+    rain_sum: Vec<Option<f32>>,
+    snowfall_sum: Vec<Option<f32>>,
+    weather_code: Vec<Option<u32>>,
+}
+
+fn value_at<T: Copy>(values: &[Option<T>], idx: usize) -> Option<T> {
+    values.get(idx).copied().flatten()
+}
+
+// Snowfall is measured in cm of snow, the rest of the providers report it in mm
+fn snowfall_in_mm(snowfall: f32) -> f32 {
+    snowfall * 10.0
+}
+
+pub struct OpenMeteoProvider {
+    client: reqwest::Client,
+    guard: UpstreamGuard,
+    forecast_url: String,
+    archive_url: String,
+}
+
+impl OpenMeteoProvider {
+    pub const NAME: &'static str = "Open-Meteo";
+
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.open-meteo.com";
+
+    // Past days are served by a host of their own, a self-hosted instance serves both APIs
+    pub const DEFAULT_ARCHIVE_BASE_URL: &'static str = "https://archive-api.open-meteo.com";
+
+    const CURRENT_VARIABLES: &'static str = "temperature_2m,apparent_temperature,\
+        relative_humidity_2m,dew_point_2m,pressure_msl,cloud_cover,visibility,wind_speed_10m,\
+        wind_direction_10m,wind_gusts_10m,uv_index,rain,snowfall,weather_code,is_day";
+
+    const HOURLY_VARIABLES: &'static str = "temperature_2m,apparent_temperature,\
+        relative_humidity_2m,dew_point_2m,pressure_msl,cloud_cover,visibility,wind_speed_10m,\
+        wind_direction_10m,wind_gusts_10m,uv_index,rain,snowfall,precipitation_probability,\
+        weather_code,is_day";
+
+    const DAILY_VARIABLES: &'static str = "sunrise,sunset,temperature_2m_max,temperature_2m_min,\
+        uv_index_max,wind_speed_10m_max,wind_gusts_10m_max,wind_direction_10m_dominant,\
+        precipitation_probability_max,rain_sum,snowfall_sum,weather_code";
+
+    // The reanalysis has no visibility, UV index nor precipitation probability
+    const ARCHIVE_HOURLY_VARIABLES: &'static str = "temperature_2m,apparent_temperature,\
+        relative_humidity_2m,dew_point_2m,pressure_msl,cloud_cover,wind_speed_10m,\
+        wind_direction_10m,wind_gusts_10m,rain,snowfall,weather_code,is_day";
+
+    // The daily API has no temperatures by time of day nor means, they come from the hourly data
+    const DAILY_HOURLY_VARIABLES: &'static str = "temperature_2m,apparent_temperature,\
+        relative_humidity_2m,dew_point_2m,pressure_msl,cloud_cover";
+
+    const FORECAST_HOURS: &'static str = "48";
+
+    const FORECAST_DAYS: &'static str = "8";
+
+    // Days reach the archive a few days late, until then they're kept by the forecast API
+    const ARCHIVE_DELAY_DAYS: u32 = 5;
+
+    pub fn build(config: ProviderConfig) -> Self {
+        let base_url = config.base_url.trim_end_matches('/');
+        let archive_base_url = match base_url {
+            OpenMeteoProvider::DEFAULT_BASE_URL => OpenMeteoProvider::DEFAULT_ARCHIVE_BASE_URL,
+            base_url => base_url,
+        };
+
+        OpenMeteoProvider {
+            client: config.resilience.build_http_client(),
+            forecast_url: format!("{}/v1/forecast", base_url),
+            archive_url: format!("{}/v1/archive", archive_base_url),
+            guard: UpstreamGuard::build(OpenMeteoProvider::NAME, config.resilience),
+        }
+    }
+
+    async fn query_forecast(
+        &self,
+        url: &str,
+        lat: f32,
+        lon: f32,
+        variables: &[(&str, &str)],
+    ) -> Result<ForecastResponse, ApiError> {
+        let query_params = &[
+            ("latitude", lat.to_string()),
+            ("longitude", lon.to_string()),
+            // Days are split in local time, and every time is sent as an epoch
+            ("timezone", "auto".into()),
+            ("timeformat", "unixtime".into()),
+            ("wind_speed_unit", "ms".into()),
+        ];
+
+        let client = &self.client;
+
+        self.guard
+            .call(move || async move {
+                client
+                    .get(url)
+                    .query(query_params)
+                    .query(variables)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<ForecastResponse>()
+                    .await
+            })
+            .await
+    }
+
+    fn build_report(forecast: &ForecastResponse) -> WeatherReport {
+        WeatherReport {
+            lat: Some(forecast.latitude),
+            lon: Some(forecast.longitude),
+            timezone: forecast.timezone.clone(),
+            timezone_offset: forecast.utc_offset_seconds,
+            ..Default::default()
+        }
+    }
+
+    fn build_current(current: &CurrentData, daily: Option<&DailyData>) -> CurrentWeather {
+        CurrentWeather {
+            dt: current.time,
+            sunrise: daily.and_then(|daily| value_at(&daily.sunrise, 0)),
+            sunset: daily.and_then(|daily| value_at(&daily.sunset, 0)),
+            temperature: current.temperature_2m,
+            feels_like: Some(current.apparent_temperature),
+            pressure: current.pressure_msl,
+            humidity: current.relative_humidity_2m.round() as u32,
+            dew_point: Some(current.dew_point_2m),
+            uv_index: current.uv_index,
+            clouds: current.cloud_cover.round() as u32,
+            visibility: current.visibility,
+            wind_speed: current.wind_speed_10m,
+            wind_gust: current.wind_gusts_10m,
+            wind_direction: current.wind_direction_10m.round() as u32,
+            rain: current.rain,
+            snow: current.snowfall.map(snowfall_in_mm),
+            conditions: vec![providers::weather_condition(
+                OpenMeteoProvider::condition_id(current.weather_code),
+                current.is_day != 0,
+            )],
+        }
+    }
+
+    // Hours missing a required value are left out, the model hasn't computed them yet
+    fn build_hourly(hourly: &HourlyData) -> Vec<HourlyWeather> {
+        hourly
+            .time
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, &dt)| {
+                Some(HourlyWeather {
+                    dt,
+                    temperature: value_at(&hourly.temperature_2m, idx)?,
+                    feels_like: value_at(&hourly.apparent_temperature, idx),
+                    pressure: value_at(&hourly.pressure_msl, idx)?,
+                    humidity: value_at(&hourly.relative_humidity_2m, idx)?.round() as u32,
+                    dew_point: value_at(&hourly.dew_point_2m, idx),
+                    uv_index: value_at(&hourly.uv_index, idx),
+                    clouds: value_at(&hourly.cloud_cover, idx)?.round() as u32,
+                    visibility: value_at(&hourly.visibility, idx),
+                    wind_speed: value_at(&hourly.wind_speed_10m, idx)?,
+                    wind_gust: value_at(&hourly.wind_gusts_10m, idx),
+                    wind_direction: value_at(&hourly.wind_direction_10m, idx)?.round() as u32,
+                    rain: value_at(&hourly.rain, idx),
+                    snow: value_at(&hourly.snowfall, idx).map(snowfall_in_mm),
+                    conditions: value_at(&hourly.weather_code, idx)
+                        .zip(value_at(&hourly.is_day, idx))
+                        .map(|(code, is_day)| {
+                            providers::weather_condition(
+                                OpenMeteoProvider::condition_id(code),
+                                is_day != 0,
+                            )
+                        })
+                        .into_iter()
+                        .collect(),
+                    pop: value_at(&hourly.precipitation_probability, idx).map(|pop| pop / 100.0),
+                })
+            })
+            .collect()
+    }
+
+    // Days missing a required value are left out as well
+    fn build_daily(daily: &DailyData, hourly: &HourlyData) -> Vec<DailyWeather> {
+        daily
+            .time
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, &dt)| {
+                let day_end = daily.time.get(idx + 1).copied().unwrap_or(dt + 86_400);
+
+                // Hours of the day, found by time since days with a DST change don't have 24
+                let hours = hourly
+                    .time
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &time)| time >= dt && time < day_end)
+                    .map(|(hour_idx, _)| hour_idx)
+                    .collect::<Vec<usize>>();
+                let at_hour = |values: &[Option<f32>], hour: u32| {
+                    hourly
+                        .time
+                        .iter()
+                        .position(|&time| time >= dt + hour * 3_600)
+                        .and_then(|hour_idx| value_at(values, hour_idx))
+                };
+                let hours_mean = |values: &[Option<f32>]| {
+                    providers::mean(
+                        hours
+                            .iter()
+                            .filter_map(|&hour_idx| value_at(values, hour_idx)),
+                    )
+                };
+
+                let feels_like =
+                    match [6, 12, 18, 0].map(|hour| at_hour(&hourly.apparent_temperature, hour)) {
+                        [Some(morn), Some(day), Some(eve), Some(night)] => Some(DailyFeelsLike {
+                            morn,
+                            day,
+                            eve,
+                            night,
+                        }),
+                        _ => None,
+                    };
+
+                Some(DailyWeather {
+                    dt,
+                    sunrise: value_at(&daily.sunrise, idx),
+                    sunset: value_at(&daily.sunset, idx),
+                    moonrise: None,
+                    moonset: None,
+                    moon_phase: None,
+                    temperature: DailyTemperature {
+                        min: value_at(&daily.temperature_2m_min, idx)?,
+                        max: value_at(&daily.temperature_2m_max, idx)?,
+                        morn: at_hour(&hourly.temperature_2m, 6)?,
+                        day: at_hour(&hourly.temperature_2m, 12)?,
+                        eve: at_hour(&hourly.temperature_2m, 18)?,
+                        night: at_hour(&hourly.temperature_2m, 0)?,
+                    },
+                    feels_like,
+                    pressure: hours_mean(&hourly.pressure_msl)?,
+                    humidity: hours_mean(&hourly.relative_humidity_2m)?.round() as u32,
+                    dew_point: hours_mean(&hourly.dew_point_2m),
+                    uv_index: value_at(&daily.uv_index_max, idx),
+                    clouds: hours_mean(&hourly.cloud_cover)?.round() as u32,
+                    wind_speed: value_at(&daily.wind_speed_10m_max, idx)?,
+                    wind_gust: value_at(&daily.wind_gusts_10m_max, idx),
+                    wind_direction: value_at(&daily.wind_direction_10m_dominant, idx)?.round()
+                        as u32,
+                    conditions: value_at(&daily.weather_code, idx)
+                        .map(|code| {
+                            providers::weather_condition(
+                                OpenMeteoProvider::condition_id(code),
+                                true,
+                            )
+                        })
+                        .into_iter()
+                        .collect(),
+                    pop: value_at(&daily.precipitation_probability_max, idx).map(|pop| pop / 100.0),
+                    rain: value_at(&daily.rain_sum, idx),
+                    snow: value_at(&daily.snowfall_sum, idx).map(snowfall_in_mm),
+                })
+            })
+            .collect()
+    }
+
+    // WMO weather interpretation codes to their OpenWeatherMap equivalent
+    fn condition_id(code: u32) -> u32 {
+        match code {
+            0 => 800,
+            1 => 801,
+            2 => 802,
+            3 => 804,
+            45 | 48 => 741,
+            51 => 300,
+            53 | 56 => 301,
+            55 | 57 => 302,
+            61 => 500,
+            63 => 501,
+            65 => 502,
+            66 | 67 => 511,
+            71 | 77 => 600,
+            73 => 601,
+            75 => 602,
+            80 => 520,
+            81 => 521,
+            82 => 522,
+            85 => 620,
+            86 => 621,
+            95 => 211,
+            96 | 99 => 202,
+            _ => 800,
+        }
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for OpenMeteoProvider {
+    fn name(&self) -> &'static str {
+        OpenMeteoProvider::NAME
+    }
+
+    fn supports(&self, request_type: RequestType) -> bool {
+        matches!(
+            request_type,
+            RequestType::CurrentWeather
+                | RequestType::WeatherForecast
+                | RequestType::DailyForecast
+                | RequestType::Historical(_)
+        )
+    }
+
+    async fn fetch(
+        &self,
+        request_type: RequestType,
+        lat: f32,
+        lon: f32,
+    ) -> Result<WeatherReport, ApiError> {
+        match request_type {
+            RequestType::CurrentWeather => {
+                let forecast = self
+                    .query_forecast(
+                        &self.forecast_url,
+                        lat,
+                        lon,
+                        &[
+                            ("current", OpenMeteoProvider::CURRENT_VARIABLES),
+                            ("daily", "sunrise,sunset"),
+                            ("forecast_days", "1"),
+                        ],
+                    )
+                    .await?;
+
+                let current = forecast.current.as_ref().ok_or_else(|| {
+                    ApiError::UpstreamError("Open-Meteo sent no current weather".into())
+                })?;
+
+                Ok(WeatherReport {
+                    current: Some(OpenMeteoProvider::build_current(
+                        current,
+                        forecast.daily.as_ref(),
+                    )),
+                    ..OpenMeteoProvider::build_report(&forecast)
+                })
+            }
+            RequestType::WeatherForecast => {
+                let forecast = self
+                    .query_forecast(
+                        &self.forecast_url,
+                        lat,
+                        lon,
+                        &[
+                            ("hourly", OpenMeteoProvider::HOURLY_VARIABLES),
+                            ("forecast_hours", OpenMeteoProvider::FORECAST_HOURS),
+                            ("forecast_days", OpenMeteoProvider::FORECAST_DAYS),
+                        ],
+                    )
+                    .await?;
+
+                Ok(WeatherReport {
+                    hourly: forecast
+                        .hourly
+                        .as_ref()
+                        .map(OpenMeteoProvider::build_hourly),
+                    ..OpenMeteoProvider::build_report(&forecast)
+                })
+            }
+            RequestType::Historical(day) => {
+                let date = request::format_date(day);
+                let (url, variables) =
+                    if day + OpenMeteoProvider::ARCHIVE_DELAY_DAYS < utils::current_epoch_day() {
+                        (
+                            &self.archive_url,
+                            OpenMeteoProvider::ARCHIVE_HOURLY_VARIABLES,
+                        )
+                    } else {
+                        (&self.forecast_url, OpenMeteoProvider::HOURLY_VARIABLES)
+                    };
+
+                let forecast = self
+                    .query_forecast(
+                        url,
+                        lat,
+                        lon,
+                        &[
+                            ("hourly", variables),
+                            ("start_date", &date),
+                            ("end_date", &date),
+                        ],
+                    )
+                    .await?;
+
+                Ok(WeatherReport {
+                    hourly: forecast
+                        .hourly
+                        .as_ref()
+                        .map(OpenMeteoProvider::build_hourly),
+                    ..OpenMeteoProvider::build_report(&forecast)
+                })
+            }
+            RequestType::DailyForecast => {
+                let forecast = self
+                    .query_forecast(
+                        &self.forecast_url,
+                        lat,
+                        lon,
+                        &[
+                            ("daily", OpenMeteoProvider::DAILY_VARIABLES),
+                            ("hourly", OpenMeteoProvider::DAILY_HOURLY_VARIABLES),
+                            ("forecast_days", OpenMeteoProvider::FORECAST_DAYS),
+                        ],
+                    )
+                    .await?;
+
+                let daily = forecast.daily.as_ref().map(|daily| {
+                    OpenMeteoProvider::build_daily(
+                        daily,
+                        forecast.hourly.as_ref().unwrap_or(&HourlyData::default()),
+                    )
+                });
+
+                Ok(WeatherReport {
+                    daily,
+                    ..OpenMeteoProvider::build_report(&forecast)
+                })
+            }
+            _ => Err(ApiError::Unsupported(format!(
+                "Open-Meteo has no {} data",
+                request_type.name()
+            ))),
+        }
+    }
+
+    fn circuit_status(&self) -> CircuitStatus {
+        self.guard.status()
+    }
+}
+
+#[cfg(test)]
+mod test_open_meteo {
+    use super::*;
+
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use std::collections::HashMap;
+
+    use crate::resilience::ResilienceConfig;
+
+    fn bad_request(reason: String) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(serde_json::json!({ "error": true, "reason": reason }).to_string())
+    }
+
+    // Dates outside of the range an API keeps are rejected, like the upstream does
+    fn check_dates(query: &HashMap<String, String>, first: u32, last: u32) -> Option<String> {
+        ["start_date", "end_date"].iter().find_map(|param| {
+            let day = request::parse_date(query.get(*param)?)?;
+
+            (day < first || day > last).then(|| {
+                format!(
+                    "Parameter '{}' is out of allowed range from {} to {}",
+                    param,
+                    request::format_date(first),
+                    request::format_date(last)
+                )
+            })
+        })
+    }
+
+    // Recorded replies, picked by the variables requested
+    async fn forecast_fixture(
+        req: HttpRequest,
+        query: web::Query<HashMap<String, String>>,
+    ) -> HttpResponse {
+        let today = utils::current_epoch_day();
+        if let Some(reason) = check_dates(&query, today - 92, today + 16) {
+            return bad_request(reason);
+        }
+
+        let query = req.query_string();
+
+        let body = if query.contains("current=") {
+            include_str!("../../tests/fixtures/open_meteo_current.json")
+        } else if query.contains("daily=sunrise%2Csunset%2Ctemperature") {
+            include_str!("../../tests/fixtures/open_meteo_daily.json")
+        } else if query.contains("start_date=") {
+            // Recorded for 2026-10-14, served for every recent day
+            include_str!("../../tests/fixtures/open_meteo_history.json")
+        } else if query.contains("forecast_hours=48") {
+            include_str!("../../tests/fixtures/open_meteo_hourly.json")
+        } else {
+            return bad_request("Unexpected query".into());
+        };
+
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(body)
+    }
+
+    async fn archive_fixture(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        // It reaches back to 1940, before any date that can be requested
+        if let Some(reason) = check_dates(&query, 0, utils::current_epoch_day()) {
+            return bad_request(reason);
+        }
+
+        let hourly = query.get("hourly").map(String::as_str).unwrap_or_default();
+        let archived = OpenMeteoProvider::ARCHIVE_HOURLY_VARIABLES
+            .split(',')
+            .collect::<Vec<&str>>();
+        if let Some(variable) = hourly.split(',').find(|var| !archived.contains(var)) {
+            return bad_request(format!(
+                "Cannot initialize WeatherVariable from invalid String value {} for key hourly",
+                variable
+            ));
+        }
+
+        // Recorded for 2026-09-28, served for every day
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("../../tests/fixtures/open_meteo_archive.json"))
+    }
+
+    fn mock_provider() -> (test::TestServer, OpenMeteoProvider) {
+        let srv = test::start(|| {
+            App::new()
+                .route("/v1/forecast", web::get().to(forecast_fixture))
+                .route("/v1/archive", web::get().to(archive_fixture))
+        });
+
+        let provider = OpenMeteoProvider::build(ProviderConfig {
+            base_url: srv.url("/"),
+            resilience: ResilienceConfig::default(),
+        });
+
+        (srv, provider)
+    }
+
+    #[test]
+    fn check_weather_codes() {
+        // Every WMO code maps to a condition with a description of its own
+        for code in 0..=99 {
+            let id = OpenMeteoProvider::condition_id(code);
+
+            assert!(providers::condition_description(id).is_some(), "{}", id);
+        }
+
+        assert_eq!(OpenMeteoProvider::condition_id(95), 211);
+        assert_eq!(OpenMeteoProvider::condition_id(99), 202);
+    }
+
+    #[actix_rt::test]
+    async fn check_current_weather() {
+        let (_srv, provider) = mock_provider();
+
+        let response = provider
+            .fetch(RequestType::CurrentWeather, 40.42, -3.7)
+            .await
+            .unwrap();
+
+        assert_eq!(response.timezone.as_deref(), Some("Europe/Madrid"));
+        assert_eq!(response.timezone_offset, Some(7_200));
+
+        let current = response.current.unwrap();
+
+        assert_eq!(current.dt, 1_792_151_100);
+        assert_eq!(current.temperature, 16.5);
+        assert_eq!(current.feels_like, Some(13.6));
+        assert_eq!(current.humidity, 85);
+        assert_eq!(current.wind_direction, 256);
+        assert_eq!(current.sunrise, Some(1_792_131_960));
+        assert_eq!(current.rain, Some(0.5));
+        assert_eq!(current.snow, Some(0.0));
+
+        let condition = &current.conditions[0];
+
+        assert_eq!(condition.id, 500);
+        assert_eq!(condition.icon, "10d");
+    }
+
+    #[actix_rt::test]
+    async fn check_forecasts() {
+        let (_srv, provider) = mock_provider();
+
+        let response = provider
+            .fetch(RequestType::WeatherForecast, 40.42, -3.7)
+            .await
+            .unwrap();
+        let hourly = response.hourly.unwrap();
+
+        assert_eq!(hourly.len(), 48);
+        assert_eq!(hourly[1].dt - hourly[0].dt, 3_600);
+        assert_eq!(hourly[0].pop, Some(0.7));
+        assert_eq!(hourly[0].conditions.len(), 1);
+
+        // Recent days are still kept by the forecast API
+        let yesterday = utils::current_epoch_day() - 1;
+        let response = provider
+            .fetch(RequestType::Historical(yesterday), 40.42, -3.7)
+            .await
+            .unwrap();
+
+        assert_eq!(response.lat, Some(40.4375));
+
+        let hourly = response.hourly.unwrap();
+
+        assert_eq!(hourly.len(), 24);
+        assert!(hourly[12].uv_index.is_some());
+
+        // Older ones come from the archive, on the grid of the reanalysis
+        let response = provider
+            .fetch(RequestType::Historical(20_724), 40.42, -3.7)
+            .await
+            .unwrap();
+
+        assert_eq!(response.lat, Some(40.4));
+
+        let hourly = response.hourly.unwrap();
+
+        // 2026-09-28, from local midnight
+        assert_eq!(hourly.len(), 24);
+        assert_eq!(hourly[0].dt, 1_790_546_400);
+        assert!(hourly.iter().all(|hour| hour.uv_index.is_none()));
+        assert!(hourly.iter().all(|hour| hour.pop.is_none()));
+
+        // Beyond the three months the forecast API keeps
+        let response = provider
+            .fetch(RequestType::Historical(18_568), 40.42, -3.7)
+            .await;
+
+        assert!(response.is_ok());
+
+        let response = provider
+            .fetch(RequestType::DailyForecast, 40.42, -3.7)
+            .await
+            .unwrap();
+        let daily = response.daily.unwrap();
+
+        assert_eq!(daily.len(), 8);
+        assert_eq!(daily[0].temperature.max, 17.1);
+        assert_eq!(daily[0].temperature.min, 10.7);
+        assert_eq!(daily[0].pop, Some(0.7));
+        assert_eq!(daily[0].rain, Some(2.2));
+        assert_eq!(daily[0].snow, Some(0.0));
+        assert!(daily[0].moonrise.is_none());
+        // Taken from the hourly temperatures at 6, 12 and 18 local time
+        let temperature = &daily[0].temperature;
+        assert!(temperature.morn < temperature.day);
+        assert!(temperature.min <= temperature.morn);
+        assert!(temperature.day <= temperature.max);
+        assert!(daily[0].feels_like.is_some());
+
+        let error = provider
+            .fetch(RequestType::AirQuality, 40.42, -3.7)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "unsupported_request");
+        assert!(!provider.supports(RequestType::Alerts));
+    }
+}
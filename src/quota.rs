@@ -0,0 +1,220 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+use crate::utils;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotaConfig {
+    // Limits of the upstream plan, 0 leaves the calls unlimited
+    pub calls_per_minute: u32,
+    pub calls_per_day: u32,
+    // Calls beyond the per minute budget wait for it up to this long, then are rejected
+    pub max_wait_milis: u64,
+}
+
+impl QuotaConfig {
+    pub const DEFAULT_MAX_WAIT_MILIS: u64 = 1_000;
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            calls_per_minute: 0,
+            calls_per_day: 0,
+            max_wait_milis: QuotaConfig::DEFAULT_MAX_WAIT_MILIS,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct QuotaStats {
+    pub calls_per_minute: Option<u32>,
+    pub calls_per_day: Option<u32>,
+    // Upstream calls made since midnight UTC, when the daily quota resets
+    pub calls_today: u32,
+    pub remaining_today: Option<u32>,
+    pub rejected: u64,
+}
+
+struct QuotaState {
+    // Can go below zero, each call waiting on the bucket reserves its token in advance
+    tokens: f64,
+    refilled_at: Instant,
+    day: u32,
+    calls_today: u32,
+    rejected: u64,
+}
+
+// Token bucket refilled at the per minute rate, along with a counter of the calls of the day
+pub struct UpstreamQuota {
+    config: QuotaConfig,
+    state: Mutex<QuotaState>,
+}
+
+impl UpstreamQuota {
+    pub fn build(config: QuotaConfig) -> Self {
+        UpstreamQuota {
+            state: Mutex::new(QuotaState {
+                tokens: f64::from(config.calls_per_minute),
+                refilled_at: Instant::now(),
+                day: utils::current_epoch_day(),
+                calls_today: 0,
+                rejected: 0,
+            }),
+            config,
+        }
+    }
+
+    // On success returns how long the call has to wait before being made. A spent minute
+    // budget is worth retrying shortly, unlike a spent daily one
+    pub fn acquire(&self) -> Result<Duration, ApiError> {
+        let mut state = self.state.lock().unwrap();
+
+        let today = utils::current_epoch_day();
+        if state.day != today {
+            state.day = today;
+            state.calls_today = 0;
+        }
+
+        if self.config.calls_per_day > 0 && state.calls_today >= self.config.calls_per_day {
+            state.rejected += 1;
+            return Err(ApiError::QuotaExhausted(format!(
+                "The daily quota of {} upstream calls is used up",
+                self.config.calls_per_day
+            )));
+        }
+
+        let wait = if self.config.calls_per_minute > 0 {
+            let capacity = f64::from(self.config.calls_per_minute);
+            let rate_per_sec = capacity / 60.0;
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * rate_per_sec).min(capacity);
+            state.refilled_at = now;
+
+            let wait = if state.tokens >= 1.0 {
+                Duration::from_secs(0)
+            } else {
+                Duration::from_secs_f64((1.0 - state.tokens) / rate_per_sec)
+            };
+
+            if wait > Duration::from_millis(self.config.max_wait_milis) {
+                state.rejected += 1;
+                return Err(ApiError::RateLimited(format!(
+                    "The quota of {} upstream calls per minute is used up",
+                    self.config.calls_per_minute
+                )));
+            }
+
+            state.tokens -= 1.0;
+            wait
+        } else {
+            Duration::from_secs(0)
+        };
+
+        state.calls_today += 1;
+
+        Ok(wait)
+    }
+
+    pub fn stats(&self) -> QuotaStats {
+        let state = self.state.lock().unwrap();
+
+        let calls_today = if state.day == utils::current_epoch_day() {
+            state.calls_today
+        } else {
+            0
+        };
+        let limit = |calls: u32| if calls > 0 { Some(calls) } else { None };
+
+        QuotaStats {
+            calls_per_minute: limit(self.config.calls_per_minute),
+            calls_per_day: limit(self.config.calls_per_day),
+            calls_today,
+            remaining_today: limit(self.config.calls_per_day)
+                .map(|calls_per_day| calls_per_day.saturating_sub(calls_today)),
+            rejected: state.rejected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_quota {
+    use super::*;
+
+    fn build_quota(
+        calls_per_minute: u32,
+        calls_per_day: u32,
+        max_wait_milis: u64,
+    ) -> UpstreamQuota {
+        UpstreamQuota::build(QuotaConfig {
+            calls_per_minute,
+            calls_per_day,
+            max_wait_milis,
+        })
+    }
+
+    #[test]
+    fn check_minute_budget() {
+        // A token every 50ms once the full bucket is spent
+        let quota = build_quota(1200, 0, 120);
+
+        for _ in 0..1200 {
+            assert_eq!(quota.acquire(), Ok(Duration::from_secs(0)));
+        }
+
+        let first_wait = quota.acquire().unwrap();
+        let second_wait = quota.acquire().unwrap();
+
+        assert!(first_wait <= Duration::from_millis(50), "{:?}", first_wait);
+        assert!(second_wait > first_wait, "{:?}", second_wait);
+        assert!(quota.acquire().is_err());
+
+        let stats = quota.stats();
+
+        assert_eq!(stats.calls_today, 1202);
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.remaining_today, None);
+
+        // Without waiting, a call is rejected as soon as the bucket is empty
+        let quota = build_quota(1, 0, 0);
+
+        assert!(quota.acquire().is_ok());
+        assert_eq!(
+            quota.acquire(),
+            Err(ApiError::RateLimited(
+                "The quota of 1 upstream calls per minute is used up".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn check_daily_budget() {
+        let quota = build_quota(0, 3, 0);
+
+        for remaining in (0..3).rev() {
+            assert_eq!(quota.acquire(), Ok(Duration::from_secs(0)));
+            assert_eq!(quota.stats().remaining_today, Some(remaining));
+        }
+
+        assert_eq!(
+            quota.acquire(),
+            Err(ApiError::QuotaExhausted(
+                "The daily quota of 3 upstream calls is used up".into()
+            ))
+        );
+        assert_eq!(
+            quota.stats(),
+            QuotaStats {
+                calls_per_minute: None,
+                calls_per_day: Some(3),
+                calls_today: 3,
+                remaining_today: Some(0),
+                rejected: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use log::{Log, Metadata, Record};
+use std::sync::RwLock;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+// Secrets removed from every log line, like the upstream API key
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+pub fn register_secret(secret: &str) {
+    let mut secrets = SECRETS.write().unwrap();
+
+    if !secret.is_empty() && !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_owned());
+    }
+}
+
+pub fn redact(message: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        message.to_owned()
+    } else {
+        message.replace(secret, REDACTED)
+    }
+}
+
+fn redact_secrets(message: &str) -> Option<String> {
+    let secrets = SECRETS.read().unwrap();
+
+    if secrets
+        .iter()
+        .any(|secret| message.contains(secret.as_str()))
+    {
+        Some(secrets.iter().fold(message.to_owned(), |message, secret| {
+            redact(&message, secret)
+        }))
+    } else {
+        None
+    }
+}
+
+// Wraps the actual logger, so no line logged by the server or its dependencies,
+// like a request url in an http client error, ever holds a registered secret
+pub struct RedactingLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> RedactingLogger<L> {
+    pub fn new(inner: L) -> Self {
+        RedactingLogger { inner }
+    }
+}
+
+impl<L: Log> Log for RedactingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        match redact_secrets(&record.args().to_string()) {
+            Some(message) => self.inner.log(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .args(format_args!("{}", message))
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test_redact {
+    use super::*;
+
+    use log::Level;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CapturingLogger {
+        lines: Mutex<Vec<String>>,
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.lines.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn check_message_redaction() {
+        let message = "error sending request for url (http://localhost/data?appid=s3cr3t&lat=1)";
+
+        assert_eq!(
+            redact(message, "s3cr3t"),
+            "error sending request for url (http://localhost/data?appid=[REDACTED]&lat=1)"
+        );
+        assert_eq!(redact(message, ""), message);
+    }
+
+    #[test]
+    fn check_log_redaction() {
+        register_secret("log-test-secret");
+        register_secret("");
+
+        let logger = RedactingLogger::new(CapturingLogger::default());
+
+        for message in &[
+            "request to http://host/?appid=log-test-secret failed",
+            "nothing to hide",
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+
+        let lines = logger.inner.lines.lock().unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "request to http://host/?appid=[REDACTED] failed");
+        assert_eq!(lines[1], "nothing to hide");
+        assert!(lines.iter().all(|line| !line.contains("log-test-secret")));
+    }
+}
@@ -0,0 +1,538 @@
+use rand::Rng;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResilienceConfig {
+    pub connect_timeout_secs: u64,
+    // Whole request, from connecting until the body is read
+    pub request_timeout_secs: u64,
+    // Attempts made after the first one fails with a transient error
+    pub max_retries: u32,
+    pub retry_base_delay_milis: u64,
+    pub retry_max_delay_milis: u64,
+    // Consecutive failed upstream calls that open the circuit, 0 never opens it
+    pub failure_threshold: u32,
+    // Time the circuit stays open before a probe request is let through
+    pub open_milis: u64,
+}
+
+impl ResilienceConfig {
+    pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+    pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 10;
+    pub const DEFAULT_MAX_RETRIES: u32 = 2;
+    pub const DEFAULT_RETRY_BASE_DELAY_MILIS: u64 = 200;
+    pub const DEFAULT_RETRY_MAX_DELAY_MILIS: u64 = 2_000;
+    pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+    pub const DEFAULT_OPEN_MILIS: u64 = 30_000;
+
+    pub fn build_http_client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .timeout(Duration::from_secs(self.request_timeout_secs))
+            .user_agent(ResilienceConfig::USER_AGENT)
+            .build()
+            .expect("Failed to build the http client")
+    }
+
+    // Some upstreams, like MET Norway, reject requests that don't identify the application
+    pub const USER_AGENT: &'static str = concat!(
+        "weather-retrieve/",
+        env!("CARGO_PKG_VERSION"),
+        " github.com/DiD92/weather-api"
+    );
+
+    // Full jitter, so the retries of concurrent requests don't reach the upstream at once
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .retry_base_delay_milis
+            .saturating_mul(2u64.saturating_pow(attempt))
+            .min(self.retry_max_delay_milis);
+
+        Duration::from_millis(rand::thread_rng().gen_range(0, ceiling + 1))
+    }
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        ResilienceConfig {
+            connect_timeout_secs: ResilienceConfig::DEFAULT_CONNECT_TIMEOUT_SECS,
+            request_timeout_secs: ResilienceConfig::DEFAULT_REQUEST_TIMEOUT_SECS,
+            max_retries: ResilienceConfig::DEFAULT_MAX_RETRIES,
+            retry_base_delay_milis: ResilienceConfig::DEFAULT_RETRY_BASE_DELAY_MILIS,
+            retry_max_delay_milis: ResilienceConfig::DEFAULT_RETRY_MAX_DELAY_MILIS,
+            failure_threshold: ResilienceConfig::DEFAULT_FAILURE_THRESHOLD,
+            open_milis: ResilienceConfig::DEFAULT_OPEN_MILIS,
+        }
+    }
+}
+
+// Failures that may not happen again if the same request is retried
+pub fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout()
+        || err.is_connect()
+        || err.status().is_some_and(|status| {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+pub fn is_transient_code(cod: u32) -> bool {
+    cod == 429 || cod >= 500
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    // Requests fail right away without reaching the upstream
+    Open,
+    // A single probe request is let through to check if the upstream recovered
+    HalfOpen,
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    // When the circuit was opened, or when the last probe was let through
+    opened_at: Instant,
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    inner: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn build(config: &ResilienceConfig) -> Self {
+        CircuitBreaker {
+            failure_threshold: config.failure_threshold,
+            open_duration: Duration::from_millis(config.open_milis),
+            inner: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+            }),
+        }
+    }
+
+    // A probe that never reports back doesn't keep the circuit from closing,
+    // another one is let through once the open duration passes again
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open | CircuitState::HalfOpen => {
+                if inner.opened_at.elapsed() >= self.open_duration {
+                    inner.state = CircuitState::HalfOpen;
+                    inner.opened_at = Instant::now();
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.state != CircuitState::Closed {
+            log::info!("Upstream recovered, closing the circuit");
+        }
+
+        inner.state = CircuitState::Closed;
+        inner.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+
+        let should_open = match inner.state {
+            CircuitState::Closed => {
+                self.failure_threshold > 0 && inner.consecutive_failures >= self.failure_threshold
+            }
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+
+        if should_open {
+            log::warn!(
+                "Upstream failed {} consecutive times, opening the circuit for {}ms",
+                inner.consecutive_failures,
+                self.open_duration.as_millis()
+            );
+
+            inner.state = CircuitState::Open;
+            inner.opened_at = Instant::now();
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.inner.lock().unwrap().state == CircuitState::Open
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let inner = self.inner.lock().unwrap();
+
+        CircuitStatus {
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+        }
+    }
+}
+
+// What came out of a single call to an upstream
+pub enum Attempt<T> {
+    Done(T),
+    // The upstream did reply, the request itself is the problem so it's not retried
+    Rejected(ApiError),
+    // Retried with backoff, and counted against the circuit
+    Failed(ApiError),
+    // The upstream did reply, but the call can be repeated right away, e.g. with
+    // another API key. The request is the one that has to bound how often it happens
+    Repeat(ApiError),
+    // The call never reached the upstream
+    Aborted(ApiError),
+}
+
+impl<T> Attempt<T> {
+    pub fn from_request_error(err: &reqwest::Error, message: String) -> Self {
+        let error = ApiError::from_request_error(err, message);
+
+        if is_transient_error(err) {
+            Attempt::Failed(error)
+        } else {
+            Attempt::Rejected(error)
+        }
+    }
+}
+
+// Retries and circuit breaker of an upstream. Transient failures are retried with
+// backoff, while the upstream keeps failing the circuit opens and requests fail
+// right away without reaching it
+pub struct UpstreamGuard {
+    name: &'static str,
+    config: ResilienceConfig,
+    breaker: CircuitBreaker,
+}
+
+impl UpstreamGuard {
+    pub fn build(name: &'static str, config: ResilienceConfig) -> Self {
+        UpstreamGuard {
+            name,
+            breaker: CircuitBreaker::build(&config),
+            config,
+        }
+    }
+
+    // For the upstreams whose failures are all reported through the http status
+    pub async fn call<T, F, Fut>(&self, request: F) -> Result<T, ApiError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, reqwest::Error>>,
+    {
+        self.call_with(|_| {
+            let response = request();
+
+            async move {
+                match response.await {
+                    Ok(response) => Attempt::Done(response),
+                    Err(err) => Attempt::from_request_error(&err, err.to_string()),
+                }
+            }
+        })
+        .await
+    }
+
+    // Each attempt gets the error of the previous one, None for the first
+    pub async fn call_with<T, F, Fut>(&self, attempt: F) -> Result<T, ApiError>
+    where
+        F: Fn(Option<ApiError>) -> Fut,
+        Fut: Future<Output = Attempt<T>>,
+    {
+        let mut retries = 0;
+        let mut last_error = None;
+
+        loop {
+            if !self.breaker.try_acquire() {
+                return Err(ApiError::UpstreamUnavailable(format!(
+                    "{} is failing, requests to it are paused",
+                    self.name
+                )));
+            }
+
+            let error = match attempt(last_error.take()).await {
+                Attempt::Done(response) => {
+                    self.breaker.record_success();
+                    return Ok(response);
+                }
+                Attempt::Rejected(error) => {
+                    self.breaker.record_success();
+                    return Err(error);
+                }
+                Attempt::Aborted(error) => return Err(error),
+                Attempt::Repeat(error) => {
+                    self.breaker.record_success();
+                    last_error = Some(error);
+                    continue;
+                }
+                Attempt::Failed(error) => error,
+            };
+
+            self.breaker.record_failure();
+
+            // Once the circuit opens no retry would get through, so the failure is returned as is
+            if retries >= self.config.max_retries || self.breaker.is_open() {
+                return Err(error);
+            }
+
+            let delay = self.config.retry_delay(retries);
+            retries += 1;
+
+            log::warn!(
+                "{} request failed, retry {} in {}ms - {}",
+                self.name,
+                retries,
+                delay.as_millis(),
+                error
+            );
+
+            last_error = Some(error);
+
+            actix_web::rt::time::delay_for(delay).await;
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        self.breaker.status()
+    }
+}
+
+#[cfg(test)]
+mod test_resilience {
+    use super::*;
+
+    use actix_web::{test, web, App, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn breaker_config(failure_threshold: u32, open_milis: u64) -> ResilienceConfig {
+        ResilienceConfig {
+            failure_threshold,
+            open_milis,
+            ..ResilienceConfig::default()
+        }
+    }
+
+    #[test]
+    fn check_retry_delay() {
+        let config = ResilienceConfig {
+            retry_base_delay_milis: 100,
+            retry_max_delay_milis: 300,
+            ..ResilienceConfig::default()
+        };
+
+        for _ in 0..50 {
+            assert!(config.retry_delay(0) <= Duration::from_millis(100));
+            assert!(config.retry_delay(1) <= Duration::from_millis(200));
+            assert!(config.retry_delay(10) <= Duration::from_millis(300));
+            assert!(config.retry_delay(u32::MAX) <= Duration::from_millis(300));
+        }
+
+        assert!(is_transient_code(429));
+        assert!(is_transient_code(503));
+        assert!(!is_transient_code(401));
+        assert!(!is_transient_code(404));
+    }
+
+    #[test]
+    fn check_circuit_opening() {
+        let breaker = CircuitBreaker::build(&breaker_config(2, 50));
+
+        breaker.record_failure();
+        assert!(breaker.try_acquire());
+
+        breaker.record_success();
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+
+        breaker.record_failure();
+        assert_eq!(
+            breaker.status(),
+            CircuitStatus {
+                state: CircuitState::Open,
+                consecutive_failures: 2,
+            }
+        );
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        // A single probe gets through, and its failure opens the circuit again
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert!(!breaker.try_acquire());
+
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 0);
+        assert!(breaker.try_acquire());
+    }
+
+    #[test]
+    fn check_disabled_circuit() {
+        let breaker = CircuitBreaker::build(&breaker_config(0, 50));
+
+        for _ in 0..100 {
+            breaker.record_failure();
+        }
+
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+    }
+
+    // Replies with the status in the path, failing with a server error
+    // until it has been called the given number of times
+    async fn status_fixture(
+        path: web::Path<u16>,
+        calls: web::Data<AtomicUsize>,
+        failures: web::Data<usize>,
+    ) -> HttpResponse {
+        if calls.fetch_add(1, Ordering::SeqCst) < **failures {
+            HttpResponse::ServiceUnavailable().finish()
+        } else {
+            HttpResponse::build(actix_web::http::StatusCode::from_u16(*path).unwrap()).finish()
+        }
+    }
+
+    fn mock_upstream(failures: usize) -> (test::TestServer, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = web::Data::from(calls.clone());
+
+        let srv = test::start(move || {
+            App::new()
+                .app_data(upstream_calls.clone())
+                .app_data(web::Data::new(failures))
+                .route("/status/{status}", web::get().to(status_fixture))
+        });
+
+        (srv, calls)
+    }
+
+    async fn call_status(guard: &UpstreamGuard, url: &str) -> Result<u16, ApiError> {
+        let client = reqwest::Client::new();
+
+        guard
+            .call(|| async {
+                client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .map(|response| response.status().as_u16())
+            })
+            .await
+    }
+
+    fn guard_config(failure_threshold: u32) -> ResilienceConfig {
+        ResilienceConfig {
+            max_retries: 2,
+            retry_base_delay_milis: 10,
+            retry_max_delay_milis: 20,
+            ..breaker_config(failure_threshold, 60_000)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn check_guarded_retries() {
+        let (srv, calls) = mock_upstream(2);
+        let guard = UpstreamGuard::build("Mock", guard_config(5));
+
+        // Server errors are retried
+        assert_eq!(call_status(&guard, &srv.url("/status/200")).await, Ok(200));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(guard.status().consecutive_failures, 0);
+
+        // While client errors are the request's fault, so they are not
+        let error = call_status(&guard, &srv.url("/status/404")).await;
+
+        assert_eq!(error.err().unwrap().code(), "upstream_error");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(guard.status().state, CircuitState::Closed);
+    }
+
+    #[actix_rt::test]
+    async fn check_guarded_circuit() {
+        let (srv, calls) = mock_upstream(usize::MAX);
+        let guard = UpstreamGuard::build("Mock", guard_config(2));
+
+        // The second failure opens the circuit, so the last retry is never made
+        let error = call_status(&guard, &srv.url("/status/200"))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_error");
+        assert!(error.message().contains("503"), "{}", error.message());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(guard.status().state, CircuitState::Open);
+
+        let error = call_status(&guard, &srv.url("/status/200"))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error,
+            ApiError::UpstreamUnavailable("Mock is failing, requests to it are paused".into())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn check_repeated_attempts() {
+        let guard = UpstreamGuard::build("Mock", guard_config(1));
+        let attempts = AtomicUsize::new(0);
+
+        // Repeats are neither retries nor failures, and the next attempt gets their error
+        let result = guard
+            .call_with(|last_error| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+
+                async move {
+                    match (attempt, last_error) {
+                        (0, None) => Attempt::Repeat(ApiError::RateLimited("Key spent".into())),
+                        (1, Some(error)) => Attempt::Aborted(error),
+                        _ => Attempt::Done(()),
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(result, Err(ApiError::RateLimited("Key spent".into())));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(guard.status().state, CircuitState::Closed);
+    }
+}
@@ -0,0 +1,38 @@
+use actix_web::{test, App};
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::net::SocketAddr;
+
+use crate::{configure_routes, SharedState};
+
+// Serves the routes of the API over the given state
+pub fn start_app(data: SharedState) -> test::TestServer {
+    test::start(move || {
+        App::new()
+            .app_data(data.clone())
+            .configure(configure_routes)
+    })
+}
+
+// An upstream refusing every connection. Its port stays bound without ever listening,
+// so no other server can take it while the upstream is alive
+pub struct RefusingUpstream {
+    socket: Socket,
+}
+
+impl RefusingUpstream {
+    pub fn start() -> Self {
+        let socket = Socket::new(Domain::ipv4(), Type::stream(), None).unwrap();
+        let address: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+        socket.bind(&SockAddr::from(address)).unwrap();
+
+        RefusingUpstream { socket }
+    }
+
+    pub fn url(&self) -> String {
+        format!(
+            "http://{}",
+            self.socket.local_addr().unwrap().as_std().unwrap()
+        )
+    }
+}
@@ -0,0 +1,528 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use crate::models::{
+    api::{
+        APIResponse, DailyFeelsLike, DailyTemperature, PrecipitationVolume, WeatherCurrent,
+        WeatherDaily, WeatherHourly,
+    },
+    request::TemperatureFormat,
+};
+
+// Responses are fetched and cached in these units, the rest are converted when served.
+// Those are °C, m/s, hPa, meters and millimeters, the ones in UnitSelection::METRIC
+pub const CANONICAL_FORMAT: TemperatureFormat = TemperatureFormat::Metric;
+
+// Same precision as the values sent by the upstream
+fn round(value: f32, decimals: i32) -> f32 {
+    let factor = 10f32.powi(decimals);
+    (value * factor).round() / factor
+}
+
+// Each unit is serialized as its label and parsed from it or any of its other names
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    #[serde(rename = "°C")]
+    Celsius,
+    #[serde(rename = "°F")]
+    Fahrenheit,
+    #[serde(rename = "K")]
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "c" | "°c" | "celsius" => Some(TemperatureUnit::Celsius),
+            "f" | "°f" | "fahrenheit" => Some(TemperatureUnit::Fahrenheit),
+            "k" | "kelvin" => Some(TemperatureUnit::Kelvin),
+            _ => None,
+        }
+    }
+
+    pub fn convert(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => round(celsius * 9.0 / 5.0 + 32.0, 2),
+            TemperatureUnit::Kelvin => round(celsius + 273.15, 2),
+        }
+    }
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpeedUnit {
+    #[serde(rename = "m/s")]
+    MetersPerSecond,
+    #[serde(rename = "km/h")]
+    KilometersPerHour,
+    #[serde(rename = "mph")]
+    MilesPerHour,
+    #[serde(rename = "kn")]
+    Knots,
+}
+
+impl SpeedUnit {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "m/s" | "ms" | "mps" => Some(SpeedUnit::MetersPerSecond),
+            "km/h" | "kmh" | "kph" => Some(SpeedUnit::KilometersPerHour),
+            "mph" => Some(SpeedUnit::MilesPerHour),
+            "kn" | "kt" | "knots" => Some(SpeedUnit::Knots),
+            _ => None,
+        }
+    }
+
+    pub fn convert(self, meters_sec: f32) -> f32 {
+        match self {
+            SpeedUnit::MetersPerSecond => meters_sec,
+            SpeedUnit::KilometersPerHour => round(meters_sec * 3.6, 2),
+            SpeedUnit::MilesPerHour => round(meters_sec * 2.236_936, 2),
+            SpeedUnit::Knots => round(meters_sec * 1.943_844, 2),
+        }
+    }
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PressureUnit {
+    #[serde(rename = "hPa")]
+    Hectopascals,
+    #[serde(rename = "inHg")]
+    InchesOfMercury,
+    #[serde(rename = "mmHg")]
+    MillimetersOfMercury,
+}
+
+impl PressureUnit {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hpa" | "mbar" => Some(PressureUnit::Hectopascals),
+            "inhg" => Some(PressureUnit::InchesOfMercury),
+            "mmhg" => Some(PressureUnit::MillimetersOfMercury),
+            _ => None,
+        }
+    }
+
+    pub fn convert(self, hectopascals: f32) -> f32 {
+        match self {
+            PressureUnit::Hectopascals => hectopascals,
+            PressureUnit::InchesOfMercury => round(hectopascals * 0.029_53, 2),
+            PressureUnit::MillimetersOfMercury => round(hectopascals * 0.750_062, 1),
+        }
+    }
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DistanceUnit {
+    #[serde(rename = "m")]
+    Meters,
+    #[serde(rename = "km")]
+    Kilometers,
+    #[serde(rename = "mi")]
+    Miles,
+}
+
+impl DistanceUnit {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "m" | "meters" => Some(DistanceUnit::Meters),
+            "km" | "kilometers" => Some(DistanceUnit::Kilometers),
+            "mi" | "miles" => Some(DistanceUnit::Miles),
+            _ => None,
+        }
+    }
+
+    pub fn convert(self, meters: f32) -> f32 {
+        match self {
+            DistanceUnit::Meters => meters,
+            DistanceUnit::Kilometers => round(meters / 1000.0, 2),
+            DistanceUnit::Miles => round(meters / 1609.344, 2),
+        }
+    }
+}
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrecipitationUnit {
+    #[serde(rename = "mm")]
+    Millimeters,
+    #[serde(rename = "in")]
+    Inches,
+}
+
+impl PrecipitationUnit {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mm" | "millimeters" => Some(PrecipitationUnit::Millimeters),
+            "in" | "inches" => Some(PrecipitationUnit::Inches),
+            _ => None,
+        }
+    }
+
+    // A tenth of a millimeter is already a hundredth of an inch, so one more decimal is kept
+    pub fn convert(self, millimeters: f32) -> f32 {
+        match self {
+            PrecipitationUnit::Millimeters => millimeters,
+            PrecipitationUnit::Inches => round(millimeters / 25.4, 3),
+        }
+    }
+}
+
+// Accepted either as the name of a temperature unit, which picks the unit system
+// it belongs to, or as an object with the unit of each quantity. Query strings can't
+// hold objects, so there it can also be a list like "temperature:C,wind_speed:kn"
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "UnitsParameter")]
+pub struct UnitSelection {
+    pub temperature: TemperatureUnit,
+    pub wind_speed: SpeedUnit,
+    pub pressure: PressureUnit,
+    pub distance: DistanceUnit,
+    pub precipitation: PrecipitationUnit,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UnitsParameter {
+    Name(String),
+    Selection(UnitNames),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct UnitNames {
+    temperature: Option<String>,
+    wind_speed: Option<String>,
+    pressure: Option<String>,
+    distance: Option<String>,
+    precipitation: Option<String>,
+}
+
+impl UnitNames {
+    fn from_list(list: &str) -> Result<Self, String> {
+        let mut names = UnitNames::default();
+
+        for entry in list.split(',') {
+            let (quantity, unit) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid units entry - {}", entry))?;
+
+            let name = match quantity.trim() {
+                "temperature" => &mut names.temperature,
+                "wind_speed" => &mut names.wind_speed,
+                "pressure" => &mut names.pressure,
+                "distance" => &mut names.distance,
+                "precipitation" => &mut names.precipitation,
+                quantity => return Err(format!("Unknown units quantity - {}", quantity)),
+            };
+
+            *name = Some(unit.trim().to_owned());
+        }
+
+        Ok(names)
+    }
+}
+
+fn parse_unit<T>(
+    quantity: &str,
+    name: Option<String>,
+    parse: fn(&str) -> Option<T>,
+    default: T,
+) -> Result<T, String> {
+    match name {
+        Some(name) => parse(&name.to_lowercase()).ok_or_else(|| {
+            log::warn!("Invalid {} unit supplied - {}", quantity, name);
+            format!("Invalid {} unit - {}", quantity, name)
+        }),
+        None => Ok(default),
+    }
+}
+
+impl TryFrom<UnitsParameter> for UnitSelection {
+    type Error = String;
+
+    fn try_from(parameter: UnitsParameter) -> Result<Self, Self::Error> {
+        let names = match parameter {
+            UnitsParameter::Name(list) if list.contains(':') => UnitNames::from_list(&list)?,
+            UnitsParameter::Name(name) => UnitNames {
+                temperature: Some(name),
+                ..Default::default()
+            },
+            UnitsParameter::Selection(names) => names,
+        };
+
+        // Quantities left out use the units of the system the temperature unit belongs to
+        let temperature = parse_unit(
+            "temperature",
+            names.temperature,
+            TemperatureUnit::parse,
+            TemperatureUnit::Celsius,
+        )?;
+        let system = UnitSelection::for_temperature(temperature);
+
+        Ok(UnitSelection {
+            temperature,
+            wind_speed: parse_unit(
+                "wind speed",
+                names.wind_speed,
+                SpeedUnit::parse,
+                system.wind_speed,
+            )?,
+            pressure: parse_unit(
+                "pressure",
+                names.pressure,
+                PressureUnit::parse,
+                system.pressure,
+            )?,
+            distance: parse_unit(
+                "distance",
+                names.distance,
+                DistanceUnit::parse,
+                system.distance,
+            )?,
+            precipitation: parse_unit(
+                "precipitation",
+                names.precipitation,
+                PrecipitationUnit::parse,
+                system.precipitation,
+            )?,
+        })
+    }
+}
+
+impl UnitSelection {
+    pub const METRIC: UnitSelection = UnitSelection {
+        temperature: TemperatureUnit::Celsius,
+        wind_speed: SpeedUnit::MetersPerSecond,
+        pressure: PressureUnit::Hectopascals,
+        distance: DistanceUnit::Meters,
+        precipitation: PrecipitationUnit::Millimeters,
+    };
+
+    // Same units as the OpenWeatherMap imperial system, only temperature and wind speed change
+    pub const IMPERIAL: UnitSelection = UnitSelection {
+        temperature: TemperatureUnit::Fahrenheit,
+        wind_speed: SpeedUnit::MilesPerHour,
+        ..UnitSelection::METRIC
+    };
+
+    pub const STANDARD: UnitSelection = UnitSelection {
+        temperature: TemperatureUnit::Kelvin,
+        ..UnitSelection::METRIC
+    };
+
+    pub fn for_temperature(temperature: TemperatureUnit) -> Self {
+        match temperature {
+            TemperatureUnit::Celsius => UnitSelection::METRIC,
+            TemperatureUnit::Fahrenheit => UnitSelection::IMPERIAL,
+            TemperatureUnit::Kelvin => UnitSelection::STANDARD,
+        }
+    }
+
+    pub fn convert_response(&self, mut response: APIResponse) -> APIResponse {
+        if *self == UnitSelection::METRIC {
+            return response;
+        }
+
+        if let Some(current) = response.current.as_mut() {
+            self.convert_current(current);
+        }
+
+        for hour in response.hourly.iter_mut().flatten() {
+            self.convert_hourly(hour);
+        }
+
+        for day in response.daily.iter_mut().flatten() {
+            self.convert_daily(day);
+        }
+
+        for minute in response.minutely.iter_mut().flatten() {
+            minute.precipitation = self.precipitation.convert(minute.precipitation);
+        }
+
+        response
+    }
+
+    fn convert_volume(&self, volume: &mut Option<PrecipitationVolume>) {
+        if let Some(volume) = volume {
+            volume.one_hour = self.precipitation.convert(volume.one_hour);
+        }
+    }
+
+    fn convert_current(&self, current: &mut WeatherCurrent) {
+        current.temp = self.temperature.convert(current.temp);
+        current.feels_like = current
+            .feels_like
+            .map(|temp| self.temperature.convert(temp));
+        current.dew_point = current.dew_point.map(|temp| self.temperature.convert(temp));
+        current.pressure = self.pressure.convert(current.pressure);
+        current.visibility = current
+            .visibility
+            .map(|visibility| self.distance.convert(visibility));
+        current.wind_speed = self.wind_speed.convert(current.wind_speed);
+        current.wind_gust = current.wind_gust.map(|gust| self.wind_speed.convert(gust));
+        self.convert_volume(&mut current.rain);
+        self.convert_volume(&mut current.snow);
+    }
+
+    fn convert_hourly(&self, hour: &mut WeatherHourly) {
+        hour.temp = self.temperature.convert(hour.temp);
+        hour.feels_like = hour.feels_like.map(|temp| self.temperature.convert(temp));
+        hour.dew_point = hour.dew_point.map(|temp| self.temperature.convert(temp));
+        hour.pressure = self.pressure.convert(hour.pressure);
+        hour.visibility = hour
+            .visibility
+            .map(|visibility| self.distance.convert(visibility));
+        hour.wind_speed = self.wind_speed.convert(hour.wind_speed);
+        hour.wind_gust = hour.wind_gust.map(|gust| self.wind_speed.convert(gust));
+        self.convert_volume(&mut hour.rain);
+        self.convert_volume(&mut hour.snow);
+    }
+
+    fn convert_daily(&self, day: &mut WeatherDaily) {
+        let DailyTemperature {
+            min,
+            max,
+            morn,
+            day: midday,
+            eve,
+            night,
+        } = &mut day.temp;
+
+        for temp in [min, max, morn, midday, eve, night] {
+            *temp = self.temperature.convert(*temp);
+        }
+
+        if let Some(DailyFeelsLike {
+            morn,
+            day: midday,
+            eve,
+            night,
+        }) = &mut day.feels_like
+        {
+            for temp in [morn, midday, eve, night] {
+                *temp = self.temperature.convert(*temp);
+            }
+        }
+
+        day.dew_point = day.dew_point.map(|temp| self.temperature.convert(temp));
+        day.pressure = self.pressure.convert(day.pressure);
+        day.wind_speed = self.wind_speed.convert(day.wind_speed);
+        day.wind_gust = day.wind_gust.map(|gust| self.wind_speed.convert(gust));
+        day.rain = day.rain.map(|rain| self.precipitation.convert(rain));
+        day.snow = day.snow.map(|snow| self.precipitation.convert(snow));
+    }
+}
+
+#[cfg(test)]
+mod test_units {
+    use super::*;
+
+    fn response() -> APIResponse {
+        serde_json::from_str(include_str!("../tests/fixtures/onecall.json")).unwrap()
+    }
+
+    #[test]
+    fn check_conversions() {
+        assert_eq!(TemperatureUnit::Celsius.convert(13.52), 13.52);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(13.52), 56.34);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(-40.0), -40.0);
+        assert_eq!(TemperatureUnit::Kelvin.convert(13.52), 286.67);
+
+        assert_eq!(SpeedUnit::MilesPerHour.convert(3.6), 8.05);
+        assert_eq!(SpeedUnit::KilometersPerHour.convert(3.6), 12.96);
+        assert_eq!(SpeedUnit::Knots.convert(3.6), 7.0);
+
+        assert_eq!(PressureUnit::InchesOfMercury.convert(1013.0), 29.91);
+        assert_eq!(PressureUnit::MillimetersOfMercury.convert(1013.0), 759.8);
+
+        assert_eq!(DistanceUnit::Kilometers.convert(10_000.0), 10.0);
+        assert_eq!(DistanceUnit::Miles.convert(10_000.0), 6.21);
+
+        assert_eq!(PrecipitationUnit::Inches.convert(2.51), 0.099);
+    }
+
+    #[test]
+    fn check_units_parsing() {
+        let parse = |json: &str| serde_json::from_str::<UnitSelection>(json);
+
+        // The temperature unit alone picks the whole unit system
+        assert_eq!(parse(r#""C""#).unwrap(), UnitSelection::METRIC);
+        assert_eq!(parse(r#""fahrenheit""#).unwrap(), UnitSelection::IMPERIAL);
+        assert_eq!(parse(r#""K""#).unwrap(), UnitSelection::STANDARD);
+
+        let units = parse(r#"{"temperature": "C", "wind_speed": "km/h"}"#).unwrap();
+        assert_eq!(units.temperature, TemperatureUnit::Celsius);
+        assert_eq!(units.wind_speed, SpeedUnit::KilometersPerHour);
+        assert_eq!(units.pressure, PressureUnit::Hectopascals);
+
+        let units = parse(r#"{"wind_speed": "kt", "pressure": "inHg", "distance": "mi"}"#).unwrap();
+        assert_eq!(units.temperature, TemperatureUnit::Celsius);
+        assert_eq!(units.wind_speed, SpeedUnit::Knots);
+        assert_eq!(units.pressure, PressureUnit::InchesOfMercury);
+        assert_eq!(units.distance, DistanceUnit::Miles);
+
+        let units = parse(r#""temperature:F,precipitation:mm""#).unwrap();
+        assert_eq!(units.wind_speed, SpeedUnit::MilesPerHour);
+        assert_eq!(units.precipitation, PrecipitationUnit::Millimeters);
+
+        assert!(parse(r#""X""#).is_err());
+        assert!(parse(r#"{"wind_speed": "furlongs"}"#).is_err());
+        assert!(parse(r#"{"altitude": "m"}"#).is_err());
+        assert!(parse(r#""temperature:C,altitude:m""#).is_err());
+
+        // Labels are valid names as well, so the units served can be read back
+        let units =
+            parse(r#"{"temperature": "C", "wind_speed": "knots", "pressure": "mmHg"}"#).unwrap();
+        let labels = serde_json::to_string(&units).unwrap();
+        assert_eq!(
+            labels,
+            r#"{"temperature":"°C","wind_speed":"kn","pressure":"mmHg","distance":"m","precipitation":"mm"}"#
+        );
+        assert_eq!(parse(&labels).unwrap(), units);
+    }
+
+    #[test]
+    fn check_response_conversion() {
+        let response = UnitSelection::IMPERIAL.convert_response(response());
+
+        let current = response.current.as_ref().unwrap();
+        assert_eq!(current.temp, 56.34);
+        assert_eq!(current.wind_gust, Some(20.71));
+        // Quantities the imperial system shares with the metric one are left as they are
+        assert_eq!(current.visibility, Some(10000.0));
+        assert_eq!(current.rain.as_ref().unwrap().one_hour, 0.21);
+
+        let daily = response.daily.as_ref().unwrap();
+        assert_eq!(daily[0].temp.max, 60.6);
+        assert_eq!(daily[0].temp.min, 47.25);
+
+        let aviation = UnitSelection {
+            wind_speed: SpeedUnit::Knots,
+            pressure: PressureUnit::InchesOfMercury,
+            distance: DistanceUnit::Kilometers,
+            precipitation: PrecipitationUnit::Inches,
+            ..UnitSelection::METRIC
+        };
+        let response = aviation.convert_response(self::response());
+        let original = self::response();
+
+        let current = response.current.as_ref().unwrap();
+        let original_current = original.current.as_ref().unwrap();
+        assert_eq!(current.temp, original_current.temp);
+        assert_eq!(current.visibility, Some(10.0));
+        assert_eq!(
+            current.pressure,
+            PressureUnit::InchesOfMercury.convert(original_current.pressure)
+        );
+        assert_eq!(current.rain.as_ref().unwrap().one_hour, 0.008);
+
+        let hourly = response.hourly.as_ref().unwrap();
+        let original_hourly = original.hourly.as_ref().unwrap();
+        assert_eq!(
+            hourly[0].wind_speed,
+            SpeedUnit::Knots.convert(original_hourly[0].wind_speed)
+        );
+
+        let daily = response.daily.as_ref().unwrap();
+        assert_eq!(daily[0].rain, Some(0.099));
+    }
+}
@@ -1,6 +1,19 @@
+use std::fmt::Display;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::api_keys::KeyPoolConfig;
+use crate::cache::{CacheConfig, TtlConfig};
 use crate::models::state::City;
+use crate::providers::{
+    failover::FailoverProvider, met_norway::MetNorwayProvider, open_meteo::OpenMeteoProvider,
+    ProviderConfig, ProviderKind, WeatherProvider,
+};
+use crate::quota::QuotaConfig;
+use crate::redact;
+use crate::resilience::ResilienceConfig;
+use crate::weather_api::{APIClient, APIConfig};
 
 pub const APP_DEVELOPMENT_FLAG: &str = "WEATHER_API_SERVER_PROD";
 
@@ -8,15 +21,386 @@ pub fn is_app_running_in_prod() -> bool {
     std::env::var(APP_DEVELOPMENT_FLAG).is_ok()
 }
 
+pub const SECONDS_PER_DAY: u32 = 86_400;
+
+pub fn current_epoch_secs() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+pub fn current_epoch_day() -> u32 {
+    current_epoch_secs() / SECONDS_PER_DAY
+}
+
 pub const API_KEY_ENV_VAR: &str = "OPENWEATHER_API_KEY";
 
-pub fn get_api_key() -> Option<String> {
-    match std::env::var(API_KEY_ENV_VAR) {
-        Ok(api_key) => Some(api_key),
+pub const API_KEYS_ENV_VAR: &str = "OPENWEATHER_API_KEYS";
+
+// A comma separated list of keys takes precedence over the single one
+pub fn get_api_keys() -> Option<Vec<String>> {
+    let api_keys = match std::env::var(API_KEYS_ENV_VAR).or_else(|_| std::env::var(API_KEY_ENV_VAR))
+    {
+        Ok(api_keys) => api_keys
+            .split(',')
+            .map(str::trim)
+            .filter(|api_key| !api_key.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>(),
         Err(err) => {
             log::error!("api key could not be loaded - {}", err);
-            None
+            return None;
+        }
+    };
+
+    if api_keys.is_empty() {
+        log::error!("api key could not be loaded - no key was supplied");
+        return None;
+    }
+
+    for api_key in &api_keys {
+        redact::register_secret(api_key);
+    }
+
+    log::info!("Using {} OpenWeatherMap API keys", api_keys.len());
+
+    Some(api_keys)
+}
+
+pub const API_BASE_URL_ENV_VAR: &str = "OPENWEATHER_API_BASE_URL";
+
+pub const API_VERSION_ENV_VAR: &str = "OPENWEATHER_API_VERSION";
+
+pub fn get_api_config() -> Option<APIConfig> {
+    let base_url =
+        std::env::var(API_BASE_URL_ENV_VAR).unwrap_or_else(|_| APIClient::DEFAULT_BASE_URL.into());
+
+    let version = match std::env::var(API_VERSION_ENV_VAR) {
+        Ok(version) => match version.parse() {
+            Ok(version) => version,
+            Err(err) => {
+                log::error!("api version could not be loaded - {}", err);
+                return None;
+            }
+        },
+        Err(_) => APIConfig::default().version,
+    };
+
+    log::info!("Using OpenWeatherMap API {} at {}", version, base_url);
+
+    Some(APIConfig {
+        base_url,
+        version,
+        resilience: get_resilience_config()?,
+        quota: get_quota_config()?,
+        keys: get_key_pool_config()?,
+    })
+}
+
+pub const API_KEY_SELECTION_ENV_VAR: &str = "OPENWEATHER_API_KEY_SELECTION";
+
+pub const API_KEY_QUARANTINE_ENV_VAR: &str = "OPENWEATHER_API_KEY_QUARANTINE_SECS";
+
+pub fn get_key_pool_config() -> Option<KeyPoolConfig> {
+    let defaults = KeyPoolConfig::default();
+
+    let selection = match std::env::var(API_KEY_SELECTION_ENV_VAR) {
+        Ok(selection) => match selection.parse() {
+            Ok(selection) => selection,
+            Err(err) => {
+                log::error!("api key selection could not be loaded - {}", err);
+                return None;
+            }
+        },
+        Err(_) => defaults.selection,
+    };
+
+    Some(KeyPoolConfig {
+        selection,
+        quarantine_secs: load_env_number(API_KEY_QUARANTINE_ENV_VAR, defaults.quarantine_secs)?,
+    })
+}
+
+pub const WEATHER_PROVIDER_ENV_VAR: &str = "WEATHER_PROVIDER";
+
+pub const WEATHER_PROVIDERS_ENV_VAR: &str = "WEATHER_PROVIDERS";
+
+pub const OPEN_METEO_BASE_URL_ENV_VAR: &str = "OPEN_METEO_BASE_URL";
+
+pub const MET_NORWAY_BASE_URL_ENV_VAR: &str = "MET_NORWAY_BASE_URL";
+
+// A comma separated list of providers, tried in order, takes precedence over the single one
+pub fn get_provider_kinds() -> Option<Vec<ProviderKind>> {
+    let providers = match std::env::var(WEATHER_PROVIDERS_ENV_VAR)
+        .or_else(|_| std::env::var(WEATHER_PROVIDER_ENV_VAR))
+    {
+        Ok(providers) => providers,
+        Err(_) => return Some(vec![ProviderKind::OpenWeatherMap]),
+    };
+
+    let kinds = match providers
+        .split(',')
+        .filter(|provider| !provider.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<ProviderKind>, String>>()
+    {
+        Ok(kinds) => kinds,
+        Err(err) => {
+            log::error!("weather provider could not be loaded - {}", err);
+            return None;
         }
+    };
+
+    if kinds.is_empty() {
+        log::error!("weather provider could not be loaded - no provider was supplied");
+        return None;
+    }
+
+    if let Some(kind) = kinds
+        .iter()
+        .enumerate()
+        .find_map(|(idx, kind)| kinds[..idx].contains(kind).then_some(kind))
+    {
+        log::error!(
+            "weather provider could not be loaded - {} is listed twice",
+            kind
+        );
+        return None;
+    }
+
+    Some(kinds)
+}
+
+// Only OpenWeatherMap needs API keys, the other providers are free to query
+fn build_provider(kind: ProviderKind) -> Option<Box<dyn WeatherProvider>> {
+    let provider_config = |env_var: &str, default_url: &str| {
+        Some(ProviderConfig {
+            base_url: std::env::var(env_var).unwrap_or_else(|_| default_url.into()),
+            resilience: get_resilience_config()?,
+        })
+    };
+
+    let provider: Box<dyn WeatherProvider> = match kind {
+        ProviderKind::OpenWeatherMap => {
+            Box::new(APIClient::build(get_api_keys()?, get_api_config()?))
+        }
+        ProviderKind::OpenMeteo => Box::new(OpenMeteoProvider::build(provider_config(
+            OPEN_METEO_BASE_URL_ENV_VAR,
+            OpenMeteoProvider::DEFAULT_BASE_URL,
+        )?)),
+        ProviderKind::MetNorway => Box::new(MetNorwayProvider::build(provider_config(
+            MET_NORWAY_BASE_URL_ENV_VAR,
+            MetNorwayProvider::DEFAULT_BASE_URL,
+        )?)),
+    };
+
+    Some(provider)
+}
+
+pub fn load_provider() -> Option<Box<dyn WeatherProvider>> {
+    let mut providers = get_provider_kinds()?
+        .into_iter()
+        .map(build_provider)
+        .collect::<Option<Vec<Box<dyn WeatherProvider>>>>()?;
+
+    let names = providers
+        .iter()
+        .map(|provider| provider.name())
+        .collect::<Vec<&str>>();
+
+    let (primary, fallbacks) = names.split_first()?;
+
+    if fallbacks.is_empty() {
+        log::info!("Using {} as weather provider", primary);
+
+        return providers.pop();
+    }
+
+    log::info!(
+        "Using {} as weather provider, failing over to {}",
+        primary,
+        fallbacks.join(", ")
+    );
+
+    FailoverProvider::build(providers)
+        .map(|provider| Box::new(provider) as Box<dyn WeatherProvider>)
+}
+
+pub const UPSTREAM_CALLS_PER_MINUTE_ENV_VAR: &str = "UPSTREAM_CALLS_PER_MINUTE";
+
+pub const UPSTREAM_CALLS_PER_DAY_ENV_VAR: &str = "UPSTREAM_CALLS_PER_DAY";
+
+pub const UPSTREAM_QUOTA_MAX_WAIT_ENV_VAR: &str = "UPSTREAM_QUOTA_MAX_WAIT_MS";
+
+pub fn get_quota_config() -> Option<QuotaConfig> {
+    let defaults = QuotaConfig::default();
+
+    let config = QuotaConfig {
+        calls_per_minute: load_env_number(
+            UPSTREAM_CALLS_PER_MINUTE_ENV_VAR,
+            defaults.calls_per_minute,
+        )?,
+        calls_per_day: load_env_number(UPSTREAM_CALLS_PER_DAY_ENV_VAR, defaults.calls_per_day)?,
+        max_wait_milis: load_env_number(UPSTREAM_QUOTA_MAX_WAIT_ENV_VAR, defaults.max_wait_milis)?,
+    };
+
+    log::info!(
+        "Limiting upstream calls to {} per minute and {} per day (0 is unlimited)",
+        config.calls_per_minute,
+        config.calls_per_day
+    );
+
+    Some(config)
+}
+
+pub const UPSTREAM_CONNECT_TIMEOUT_ENV_VAR: &str = "UPSTREAM_CONNECT_TIMEOUT_SECS";
+
+pub const UPSTREAM_REQUEST_TIMEOUT_ENV_VAR: &str = "UPSTREAM_REQUEST_TIMEOUT_SECS";
+
+pub const UPSTREAM_MAX_RETRIES_ENV_VAR: &str = "UPSTREAM_MAX_RETRIES";
+
+pub const UPSTREAM_RETRY_BASE_DELAY_ENV_VAR: &str = "UPSTREAM_RETRY_BASE_DELAY_MS";
+
+pub const UPSTREAM_RETRY_MAX_DELAY_ENV_VAR: &str = "UPSTREAM_RETRY_MAX_DELAY_MS";
+
+pub const UPSTREAM_BREAKER_THRESHOLD_ENV_VAR: &str = "UPSTREAM_BREAKER_THRESHOLD";
+
+pub const UPSTREAM_BREAKER_OPEN_ENV_VAR: &str = "UPSTREAM_BREAKER_OPEN_SECS";
+
+pub fn get_resilience_config() -> Option<ResilienceConfig> {
+    let defaults = ResilienceConfig::default();
+
+    let config = ResilienceConfig {
+        connect_timeout_secs: load_env_number(
+            UPSTREAM_CONNECT_TIMEOUT_ENV_VAR,
+            defaults.connect_timeout_secs,
+        )?,
+        request_timeout_secs: load_env_number(
+            UPSTREAM_REQUEST_TIMEOUT_ENV_VAR,
+            defaults.request_timeout_secs,
+        )?,
+        max_retries: load_env_number(UPSTREAM_MAX_RETRIES_ENV_VAR, defaults.max_retries)?,
+        retry_base_delay_milis: load_env_number(
+            UPSTREAM_RETRY_BASE_DELAY_ENV_VAR,
+            defaults.retry_base_delay_milis,
+        )?,
+        retry_max_delay_milis: load_env_number(
+            UPSTREAM_RETRY_MAX_DELAY_ENV_VAR,
+            defaults.retry_max_delay_milis,
+        )?,
+        failure_threshold: load_env_number(
+            UPSTREAM_BREAKER_THRESHOLD_ENV_VAR,
+            defaults.failure_threshold,
+        )?,
+        open_milis: load_env_number(UPSTREAM_BREAKER_OPEN_ENV_VAR, defaults.open_milis / 1000)?
+            .saturating_mul(1000),
+    };
+
+    if config.connect_timeout_secs == 0 || config.request_timeout_secs == 0 {
+        log::error!("upstream timeouts must be greater than 0");
+        return None;
+    }
+
+    Some(config)
+}
+
+pub const CACHE_MAX_ENTRIES_ENV_VAR: &str = "CACHE_MAX_ENTRIES";
+
+pub const CACHE_MAX_BYTES_ENV_VAR: &str = "CACHE_MAX_BYTES";
+
+pub const CACHE_SWEEP_INTERVAL_ENV_VAR: &str = "CACHE_SWEEP_INTERVAL_SECS";
+
+pub fn get_cache_config() -> Option<CacheConfig> {
+    let defaults = CacheConfig::default();
+
+    let config = CacheConfig {
+        max_entries: load_env_number(CACHE_MAX_ENTRIES_ENV_VAR, defaults.max_entries)?,
+        max_bytes: load_env_number(CACHE_MAX_BYTES_ENV_VAR, defaults.max_bytes)?,
+        sweep_interval_secs: load_env_number(
+            CACHE_SWEEP_INTERVAL_ENV_VAR,
+            defaults.sweep_interval_secs,
+        )?,
+        ttls: load_ttl_config()?,
+        disk_path: std::env::var(CACHE_DISK_PATH_ENV_VAR)
+            .ok()
+            .map(PathBuf::from),
+        redis_address: std::env::var(CACHE_REDIS_URL_ENV_VAR).ok(),
+        ..defaults
+    };
+
+    if config.sweep_interval_secs == 0 {
+        log::error!("cache sweep interval must be greater than 0");
+        return None;
+    }
+
+    if config.max_entries == 0 {
+        log::error!("cache max entries must be greater than 0");
+        return None;
+    }
+
+    // Each shard gets an even share of the limit, so every shard needs at least a byte
+    if config.max_bytes < config.shards {
+        log::error!(
+            "cache max bytes must be at least the {} cache shards",
+            config.shards
+        );
+        return None;
+    }
+
+    log::info!(
+        "Caching up to {} responses or {} bytes",
+        config.max_entries,
+        config.max_bytes
+    );
+
+    Some(config)
+}
+
+pub const CACHE_DISK_PATH_ENV_VAR: &str = "CACHE_DISK_PATH";
+
+pub const CACHE_REDIS_URL_ENV_VAR: &str = "CACHE_REDIS_URL";
+
+pub const CACHE_TTL_CONFIG_ENV_VAR: &str = "CACHE_TTL_CONFIG_PATH";
+
+// Without a config file every request type uses its built-in TTLs
+pub fn load_ttl_config() -> Option<TtlConfig> {
+    match std::env::var(CACHE_TTL_CONFIG_ENV_VAR) {
+        Ok(config_path) => match std::fs::read_to_string(&config_path) {
+            Ok(str_content) => match TtlConfig::from_json(&str_content) {
+                Ok(ttl_config) => {
+                    log::info!("Cache TTLs loaded from {}", config_path);
+                    Some(ttl_config)
+                }
+                Err(err) => {
+                    log::error!("Error parsing cache TTL config file - {}", err);
+                    None
+                }
+            },
+            Err(err) => {
+                log::error!("Error reading cache TTL config file - {}", err);
+                None
+            }
+        },
+        Err(_) => Some(TtlConfig::default()),
+    }
+}
+
+// Unset variables fall back to the default, invalid ones are logged and yield None
+fn load_env_number<T>(env_var: &str, default: T) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(env_var) {
+        Ok(value) => match value.parse() {
+            Ok(number) => Some(number),
+            Err(err) => {
+                log::error!("{} could not be loaded - {}", env_var, err);
+                None
+            }
+        },
+        Err(_) => Some(default),
     }
 }
 
@@ -1,25 +1,250 @@
-use crate::models::api::APIResponse;
-use crate::models::request::TemperatureFormat;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::api_keys::{ApiKeyPool, KeyPoolConfig, KeyStats};
+use crate::error::ApiError;
+use crate::models::api::{APIResponse, AirPollutionResponse, DaySummaryResponse};
+use crate::models::request::{self, RequestType, TemperatureFormat};
+use crate::models::weather::WeatherReport;
+use crate::providers::WeatherProvider;
+use crate::quota::{QuotaConfig, QuotaStats, UpstreamQuota};
+use crate::resilience::{self, Attempt, CircuitStatus, ResilienceConfig, UpstreamGuard};
+use crate::{redact, units, utils};
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum APIVersion {
+    V2_5,
+    V3_0,
+}
+
+impl Display for APIVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            APIVersion::V2_5 => write!(f, "2.5"),
+            APIVersion::V3_0 => write!(f, "3.0"),
+        }
+    }
+}
+
+impl FromStr for APIVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "2.5" => Ok(APIVersion::V2_5),
+            "3.0" | "3" => Ok(APIVersion::V3_0),
+            other => Err(format!("Unsupported OpenWeatherMap API version {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct APIConfig {
+    // Scheme and host of the upstream, without any path, e.g. http://localhost:8081
+    pub base_url: String,
+    pub version: APIVersion,
+    pub resilience: ResilienceConfig,
+    pub quota: QuotaConfig,
+    pub keys: KeyPoolConfig,
+}
+
+impl Default for APIConfig {
+    fn default() -> Self {
+        APIConfig {
+            base_url: APIClient::DEFAULT_BASE_URL.into(),
+            version: APIVersion::V2_5,
+            resilience: ResilienceConfig::default(),
+            quota: QuotaConfig::default(),
+            keys: KeyPoolConfig::default(),
+        }
+    }
+}
 
 pub struct APIClient {
     pub client: reqwest::Client,
-    api_key: String,
+    keys: ApiKeyPool,
+    guard: UpstreamGuard,
+    quota: UpstreamQuota,
+    version: APIVersion,
+    onecall_url: String,
+    historical_url: String,
+    air_pollution_url: String,
+    air_pollution_forecast_url: String,
 }
 
 impl APIClient {
-    pub const BASE_API_URL: &'static str = "https://api.openweathermap.org/data/2.5/onecall??";
+    pub const NAME: &'static str = "OpenWeatherMap";
+
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.openweathermap.org";
+
+    pub fn build(api_keys: Vec<String>, config: APIConfig) -> Self {
+        let base_url = config.base_url.trim_end_matches('/');
+        let onecall_url = format!("{}/data/{}/onecall", base_url, config.version);
 
-    pub fn build(api_key: String) -> Self {
         APIClient {
-            client: reqwest::Client::new(),
-            api_key,
+            client: config.resilience.build_http_client(),
+            keys: ApiKeyPool::build(api_keys, config.keys),
+            guard: UpstreamGuard::build(APIClient::NAME, config.resilience),
+            quota: UpstreamQuota::build(config.quota),
+            historical_url: match config.version {
+                APIVersion::V2_5 => format!("{}/timemachine", onecall_url),
+                APIVersion::V3_0 => format!("{}/day_summary", onecall_url),
+            },
+            version: config.version,
+            onecall_url,
+            // The air pollution API is only published under 2.5
+            air_pollution_url: format!("{}/data/2.5/air_pollution", base_url),
+            air_pollution_forecast_url: format!("{}/data/2.5/air_pollution/forecast", base_url),
+        }
+    }
+
+    // Besides the http status, OpenWeatherMap reports failures through the cod of the body.
+    // Errors never hold an API key, since they end up logged and sent to the clients
+    pub async fn query(
+        &self,
+        request_type: RequestType,
+        city_lat: f32,
+        city_lon: f32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, ApiError> {
+        if let RequestType::Historical(day) = request_type {
+            if day > utils::current_epoch_day() {
+                return Err(ApiError::InvalidRequest(
+                    "Historical data is not available for future dates".into(),
+                ));
+            }
+        }
+
+        let rotations = AtomicUsize::new(0);
+        let rotations = &rotations;
+
+        self.guard
+            .call_with(move |last_error| async move {
+                let (key_index, api_key) = match self.keys.select() {
+                    Some(selected) => selected,
+                    None => {
+                        return Attempt::Aborted(last_error.unwrap_or_else(|| {
+                            ApiError::UpstreamUnavailable("Every API key is quarantined".into())
+                        }))
+                    }
+                };
+
+                // Retries are upstream calls too, so each attempt takes from the quota
+                match self.quota.acquire() {
+                    Ok(wait) if wait.as_millis() > 0 => {
+                        log::debug!("Upstream quota spent, waiting {}ms", wait.as_millis());
+                        actix_web::rt::time::delay_for(wait).await;
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        log::warn!("Upstream call rejected - {}", error.message());
+                        return Attempt::Aborted(error);
+                    }
+                }
+
+                self.keys.record_call(key_index);
+
+                let response = match self
+                    .query_once(api_key, request_type, city_lat, city_lon, temperature_units)
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(err) => {
+                        return Attempt::from_request_error(&err, self.redact(&err.to_string()))
+                    }
+                };
+
+                let cod = match response.cod {
+                    Some(cod) if cod != 200 => cod,
+                    _ => return Attempt::Done(response),
+                };
+
+                let error = ApiError::from_upstream_code(
+                    cod,
+                    response.message.map(|msg| self.redact(&msg)),
+                );
+
+                // The call is repeated right away with another key, once per key at most
+                if APIClient::is_key_rejection(cod) {
+                    self.keys.quarantine(key_index, error.message());
+
+                    if rotations.fetch_add(1, Ordering::Relaxed) < self.keys.len() {
+                        return Attempt::Repeat(error);
+                    }
+                }
+
+                if resilience::is_transient_code(cod) {
+                    Attempt::Failed(error)
+                } else {
+                    Attempt::Rejected(error)
+                }
+            })
+            .await
+    }
+
+    // The key was refused or spent its own rate limit, another account may still be served
+    fn is_key_rejection(cod: u32) -> bool {
+        cod == 401 || cod == 429
+    }
+
+    async fn query_once(
+        &self,
+        api_key: &str,
+        request_type: RequestType,
+        city_lat: f32,
+        city_lon: f32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, reqwest::Error> {
+        match request_type {
+            RequestType::CurrentWeather => {
+                self.query_current_weather(api_key, city_lat, city_lon, temperature_units)
+                    .await
+            }
+            RequestType::WeatherForecast => {
+                self.query_forecast_weather(api_key, city_lat, city_lon, temperature_units)
+                    .await
+            }
+            RequestType::DailyForecast => {
+                self.query_daily_forecast(api_key, city_lat, city_lon, temperature_units)
+                    .await
+            }
+            RequestType::Nowcast => {
+                self.query_nowcast(api_key, city_lat, city_lon, temperature_units)
+                    .await
+            }
+            RequestType::Alerts => {
+                self.query_alerts(api_key, city_lat, city_lon, temperature_units)
+                    .await
+            }
+            RequestType::Historical(day) => {
+                self.query_historical(api_key, city_lat, city_lon, day, temperature_units)
+                    .await
+            }
+            RequestType::AirQuality => self.query_air_quality(api_key, city_lat, city_lon).await,
+            RequestType::AirQualityForecast => {
+                self.query_air_quality_forecast(api_key, city_lat, city_lon)
+                    .await
+            }
         }
     }
 
-    const CURRENT_WEATHER_EXCLUDE: &'static str = "minutely,hourly,daily,alerts";
+    pub fn redact(&self, message: &str) -> String {
+        self.keys
+            .keys()
+            .fold(message.to_owned(), |message, api_key| {
+                redact::redact(&message, api_key)
+            })
+    }
+
+    // Alerts are kept so the current weather can flag when any are active
+    const CURRENT_WEATHER_EXCLUDE: &'static str = "minutely,hourly,daily";
 
     pub async fn query_current_weather(
         &self,
+        api_key: &str,
         city_lat: f32,
         city_lon: f32,
         temperature_units: TemperatureFormat,
@@ -31,10 +256,11 @@ impl APIClient {
         );
 
         self.perform_query(
+            api_key,
             city_lat,
             city_lon,
             temperature_units,
-            &APIClient::CURRENT_WEATHER_EXCLUDE,
+            APIClient::CURRENT_WEATHER_EXCLUDE,
         )
         .await
     }
@@ -43,6 +269,32 @@ impl APIClient {
 
     pub async fn query_forecast_weather(
         &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap API for coords - ({},{})",
+            city_lat,
+            city_lon
+        );
+
+        self.perform_query(
+            api_key,
+            city_lat,
+            city_lon,
+            temperature_units,
+            APIClient::FORECAST_WEATHER_EXCLUDE,
+        )
+        .await
+    }
+
+    const DAILY_FORECAST_EXCLUDE: &'static str = "current,minutely,hourly,alerts";
+
+    pub async fn query_daily_forecast(
+        &self,
+        api_key: &str,
         city_lat: f32,
         city_lon: f32,
         temperature_units: TemperatureFormat,
@@ -54,39 +306,250 @@ impl APIClient {
         );
 
         self.perform_query(
+            api_key,
             city_lat,
             city_lon,
             temperature_units,
-            &APIClient::FORECAST_WEATHER_EXCLUDE,
+            APIClient::DAILY_FORECAST_EXCLUDE,
+        )
+        .await
+    }
+
+    const NOWCAST_EXCLUDE: &'static str = "current,hourly,daily,alerts";
+
+    pub async fn query_nowcast(
+        &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap API for coords - ({},{})",
+            city_lat,
+            city_lon
+        );
+
+        self.perform_query(
+            api_key,
+            city_lat,
+            city_lon,
+            temperature_units,
+            APIClient::NOWCAST_EXCLUDE,
+        )
+        .await
+    }
+
+    const ALERTS_EXCLUDE: &'static str = "current,minutely,hourly,daily";
+
+    pub async fn query_alerts(
+        &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap API for coords - ({},{})",
+            city_lat,
+            city_lon
+        );
+
+        let mut response = self
+            .perform_query(
+                api_key,
+                city_lat,
+                city_lon,
+                temperature_units,
+                APIClient::ALERTS_EXCLUDE,
+            )
+            .await?;
+
+        // The upstream omits the block entirely when there are no alerts
+        if response.cod.is_none() && response.alerts.is_none() {
+            response.alerts = Some(vec![]);
+        }
+
+        Ok(response)
+    }
+
+    pub async fn query_historical(
+        &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+        day: u32,
+        temperature_units: TemperatureFormat,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap historical API for coords - ({},{}) on day {}",
+            city_lat,
+            city_lon,
+            day
+        );
+
+        match self.version {
+            // The 2.5 timemachine replies with the hourly observations of the whole day of dt
+            APIVersion::V2_5 => {
+                let query_params = &[
+                    ("lat", &city_lat.to_string()),
+                    ("lon", &city_lon.to_string()),
+                    ("dt", &(day * utils::SECONDS_PER_DAY).to_string()),
+                    ("units", &temperature_units.to_string()),
+                ];
+
+                self.perform_request(&self.historical_url, api_key, query_params)
+                    .await
+            }
+            // The 3.0 one only has the observation at dt, the day summary covers the whole date
+            APIVersion::V3_0 => {
+                let query_params = &[
+                    ("lat", &city_lat.to_string()),
+                    ("lon", &city_lon.to_string()),
+                    ("date", &request::format_date(day)),
+                    ("units", &temperature_units.to_string()),
+                ];
+
+                self.perform_request::<DaySummaryResponse>(
+                    &self.historical_url,
+                    api_key,
+                    query_params,
+                )
+                .await
+                .map(APIResponse::from)
+            }
+        }
+    }
+
+    pub async fn query_air_quality(
+        &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap air pollution API for coords - ({},{})",
+            city_lat,
+            city_lon
+        );
+
+        self.perform_air_query(api_key, &self.air_pollution_url, city_lat, city_lon)
+            .await
+    }
+
+    pub async fn query_air_quality_forecast(
+        &self,
+        api_key: &str,
+        city_lat: f32,
+        city_lon: f32,
+    ) -> Result<APIResponse, reqwest::Error> {
+        log::debug!(
+            "Querying OpenWeatherMap air pollution forecast API for coords - ({},{})",
+            city_lat,
+            city_lon
+        );
+
+        self.perform_air_query(
+            api_key,
+            &self.air_pollution_forecast_url,
+            city_lat,
+            city_lon,
         )
         .await
     }
 
+    async fn perform_air_query(
+        &self,
+        api_key: &str,
+        url: &str,
+        city_lat: f32,
+        city_lon: f32,
+    ) -> Result<APIResponse, reqwest::Error> {
+        let query_params = &[
+            ("lat", &city_lat.to_string()),
+            ("lon", &city_lon.to_string()),
+        ];
+
+        self.perform_request::<AirPollutionResponse>(url, api_key, query_params)
+            .await
+            .map(APIResponse::from)
+    }
+
     async fn perform_query(
         &self,
+        api_key: &str,
         city_lat: f32,
         city_lon: f32,
         temperature_units: TemperatureFormat,
         exclude_set: &str,
     ) -> Result<APIResponse, reqwest::Error> {
         let query_params = &[
-            ("appid", &self.api_key),
             ("lat", &city_lat.to_string()),
             ("lon", &city_lon.to_string()),
             ("exclude", &exclude_set.to_owned()),
             ("units", &temperature_units.to_string()),
         ];
 
-        let api_request = self
+        self.perform_request(&self.onecall_url, api_key, query_params)
+            .await
+    }
+
+    async fn perform_request<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        api_key: &str,
+        query_params: &[(&str, &String)],
+    ) -> Result<T, reqwest::Error> {
+        let response = self
             .client
-            .get(APIClient::BASE_API_URL)
+            .get(url)
+            .query(&[("appid", api_key)])
             .query(query_params)
             .send()
-            .await?
-            .json::<APIResponse>()
             .await?;
 
-        Ok(api_request)
+        // Server errors rarely come with a JSON body, they are reported by their status
+        let response = if response.status().is_server_error() {
+            response.error_for_status()?
+        } else {
+            response
+        };
+
+        response.json::<T>().await
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for APIClient {
+    fn name(&self) -> &'static str {
+        APIClient::NAME
+    }
+
+    fn supports(&self, _request_type: RequestType) -> bool {
+        true
+    }
+
+    async fn fetch(
+        &self,
+        request_type: RequestType,
+        lat: f32,
+        lon: f32,
+    ) -> Result<WeatherReport, ApiError> {
+        self.query(request_type, lat, lon, units::CANONICAL_FORMAT)
+            .await
+            .map(WeatherReport::from)
+    }
+
+    fn circuit_status(&self) -> CircuitStatus {
+        self.guard.status()
+    }
+
+    fn quota_stats(&self) -> Option<QuotaStats> {
+        Some(self.quota.stats())
+    }
+
+    fn key_stats(&self) -> Vec<KeyStats> {
+        self.keys.stats()
     }
 }
 
@@ -94,6 +557,430 @@ impl APIClient {
 mod test_api_client {
     use super::*;
 
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::resilience::CircuitState;
+
+    async fn onecall_fixture(req: HttpRequest) -> HttpResponse {
+        if req.query_string().contains("appid=mock-key") {
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(include_str!("../tests/fixtures/onecall.json"))
+        } else {
+            HttpResponse::Unauthorized()
+                .content_type("application/json")
+                .body(r#"{"cod": 401, "message": "Invalid API key."}"#)
+        }
+    }
+
+    async fn timemachine_fixture(req: HttpRequest) -> HttpResponse {
+        if req.query_string().contains("dt=1604275200") {
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(include_str!("../tests/fixtures/timemachine.json"))
+        } else {
+            HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(r#"{"cod": "400", "message": "requested time is out of allowed range"}"#)
+        }
+    }
+
+    async fn day_summary_fixture(req: HttpRequest) -> HttpResponse {
+        if req.query_string().contains("date=2020-11-02") {
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(include_str!("../tests/fixtures/day_summary.json"))
+        } else {
+            HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(r#"{"cod": 400, "message": "Invalid date format"}"#)
+        }
+    }
+
+    async fn air_pollution_fixture() -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("../tests/fixtures/air_pollution.json"))
+    }
+
+    fn mock_upstream(version: &'static str) -> test::TestServer {
+        test::start(move || {
+            App::new()
+                .route(
+                    &format!("/data/{}/onecall", version),
+                    web::get().to(onecall_fixture),
+                )
+                .route(
+                    &format!("/data/{}/onecall/timemachine", version),
+                    web::get().to(timemachine_fixture),
+                )
+                .route(
+                    &format!("/data/{}/onecall/day_summary", version),
+                    web::get().to(day_summary_fixture),
+                )
+                .route(
+                    "/data/2.5/air_pollution",
+                    web::get().to(air_pollution_fixture),
+                )
+        })
+    }
+
+    // Fails with a server error until it has been called the given number of times
+    async fn flaky_onecall_fixture(
+        req: HttpRequest,
+        calls: web::Data<AtomicUsize>,
+        failures: web::Data<usize>,
+    ) -> HttpResponse {
+        if calls.fetch_add(1, Ordering::SeqCst) < **failures {
+            HttpResponse::ServiceUnavailable().body("Service Unavailable")
+        } else {
+            onecall_fixture(req).await
+        }
+    }
+
+    fn flaky_upstream(failures: usize) -> (test::TestServer, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = web::Data::from(calls.clone());
+
+        let srv = test::start(move || {
+            App::new()
+                .app_data(upstream_calls.clone())
+                .app_data(web::Data::new(failures))
+                .route("/data/2.5/onecall", web::get().to(flaky_onecall_fixture))
+        });
+
+        (srv, calls)
+    }
+
+    fn resilient_config(srv: &test::TestServer, failure_threshold: u32) -> APIConfig {
+        APIConfig {
+            base_url: srv.url("/"),
+            resilience: ResilienceConfig {
+                max_retries: 2,
+                retry_base_delay_milis: 10,
+                retry_max_delay_milis: 20,
+                failure_threshold,
+                open_milis: 100,
+                ..ResilienceConfig::default()
+            },
+            ..APIConfig::default()
+        }
+    }
+
+    #[test]
+    fn check_api_version_parsing() {
+        assert_eq!("2.5".parse::<APIVersion>(), Ok(APIVersion::V2_5));
+        assert_eq!("3.0".parse::<APIVersion>(), Ok(APIVersion::V3_0));
+        assert!("4".parse::<APIVersion>().is_err());
+    }
+
+    #[test]
+    fn check_endpoint_urls() {
+        let client = APIClient::build(vec!["aa".into()], APIConfig::default());
+
+        assert_eq!(
+            client.onecall_url,
+            "https://api.openweathermap.org/data/2.5/onecall"
+        );
+        assert_eq!(
+            client.historical_url,
+            "https://api.openweathermap.org/data/2.5/onecall/timemachine"
+        );
+
+        let config = APIConfig {
+            base_url: "http://localhost:8081/".into(),
+            version: APIVersion::V3_0,
+            ..APIConfig::default()
+        };
+        let client = APIClient::build(vec!["aa".into()], config);
+
+        assert_eq!(client.onecall_url, "http://localhost:8081/data/3.0/onecall");
+        assert_eq!(
+            client.historical_url,
+            "http://localhost:8081/data/3.0/onecall/day_summary"
+        );
+        assert_eq!(
+            client.air_pollution_url,
+            "http://localhost:8081/data/2.5/air_pollution"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn check_configured_upstream() {
+        let srv = mock_upstream("3.0");
+
+        let config = APIConfig {
+            base_url: srv.url("/"),
+            version: APIVersion::V3_0,
+            ..APIConfig::default()
+        };
+        let client = APIClient::build(vec!["mock-key".into()], config);
+
+        let api_response = client
+            .query_current_weather("mock-key", 40.42, -3.7, TemperatureFormat::Metric)
+            .await
+            .unwrap();
+
+        assert!(api_response.current.is_some());
+
+        let api_response = client
+            .query_historical("mock-key", 40.42, -3.7, 18_568, TemperatureFormat::Metric)
+            .await
+            .unwrap();
+
+        assert_eq!(api_response.daily.unwrap()[0].dt, 1_604_275_200);
+
+        let api_response = client
+            .query_air_quality("mock-key", 40.42, -3.7)
+            .await
+            .unwrap();
+
+        assert!(api_response.air_quality.is_some());
+
+        let config = APIConfig {
+            base_url: srv.url("/"),
+            version: APIVersion::V3_0,
+            ..APIConfig::default()
+        };
+        let client = APIClient::build(vec!["wrong-key".into()], config);
+
+        let api_response = client
+            .query_current_weather("wrong-key", 40.42, -3.7, TemperatureFormat::Metric)
+            .await
+            .unwrap();
+
+        assert_eq!(api_response.cod, Some(401));
+    }
+
+    #[actix_rt::test]
+    async fn check_historical_days() {
+        let srv = mock_upstream("2.5");
+
+        let config = APIConfig {
+            base_url: srv.url("/"),
+            ..APIConfig::default()
+        };
+        let client = APIClient::build(vec!["mock-key".into()], config);
+
+        // Asked from the start of the day, so every hour of it is observed
+        let api_response = client
+            .query(
+                RequestType::Historical(18_568),
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(api_response.hourly.unwrap()[0].dt, 1_604_275_200);
+
+        let error = client
+            .query(
+                RequestType::Historical(utils::current_epoch_day() + 1),
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "invalid_request");
+        assert_eq!(client.quota_stats().unwrap().calls_today, 1);
+    }
+
+    #[actix_rt::test]
+    async fn check_key_rotation() {
+        let srv = mock_upstream("2.5");
+
+        let config = APIConfig {
+            base_url: srv.url("/"),
+            ..APIConfig::default()
+        };
+        let client = APIClient::build(vec!["wrong-key".into(), "mock-key".into()], config);
+
+        for _ in 0..3 {
+            let api_response = client
+                .query(
+                    RequestType::CurrentWeather,
+                    40.42,
+                    -3.7,
+                    TemperatureFormat::Metric,
+                )
+                .await
+                .unwrap();
+
+            assert!(api_response.current.is_some());
+        }
+
+        assert_eq!(
+            client.key_stats(),
+            vec![
+                KeyStats {
+                    index: 0,
+                    calls: 1,
+                    rejections: 1,
+                    quarantined: true,
+                },
+                KeyStats {
+                    index: 1,
+                    calls: 3,
+                    rejections: 0,
+                    quarantined: false,
+                },
+            ]
+        );
+
+        // Messages are redacted with every key of the pool
+        assert_eq!(
+            client.redact("appid=wrong-key&appid=mock-key"),
+            "appid=[REDACTED]&appid=[REDACTED]"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn check_transient_retries() {
+        let (srv, calls) = flaky_upstream(2);
+        let client = APIClient::build(vec!["mock-key".into()], resilient_config(&srv, 5));
+
+        let api_response = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .unwrap();
+
+        assert!(api_response.current.is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(client.circuit_status().state, CircuitState::Closed);
+
+        // Rejected requests are never retried
+        let client = APIClient::build(vec!["wrong-key".into()], resilient_config(&srv, 5));
+        let error = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_error");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[actix_rt::test]
+    async fn check_circuit_breaker() {
+        let (srv, calls) = flaky_upstream(4);
+        let client = APIClient::build(vec!["mock-key".into()], resilient_config(&srv, 3));
+
+        let error = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_error");
+        assert!(error.message().contains("503"), "{}", error.message());
+        assert!(!error.message().contains("mock-key"), "{}", error.message());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(client.circuit_status().state, CircuitState::Open);
+
+        // While open the upstream is never reached
+        let error = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_unavailable");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        actix_rt::time::delay_for(Duration::from_millis(120)).await;
+
+        // A failed probe opens the circuit again, and its own error is returned
+        let error = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_error");
+        assert!(error.message().contains("503"), "{}", error.message());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(client.circuit_status().state, CircuitState::Open);
+
+        actix_rt::time::delay_for(Duration::from_millis(120)).await;
+
+        // The upstream recovered, so the probe closes the circuit
+        let api_response = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .unwrap();
+
+        assert!(api_response.current.is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+        assert_eq!(
+            client.circuit_status(),
+            CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn check_circuit_opened_by_retries() {
+        let (srv, calls) = flaky_upstream(3);
+        let client = APIClient::build(vec!["mock-key".into()], resilient_config(&srv, 2));
+
+        // The retries stop as soon as the circuit opens, keeping the upstream error
+        let error = client
+            .query(
+                RequestType::CurrentWeather,
+                40.42,
+                -3.7,
+                TemperatureFormat::Metric,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.code(), "upstream_error");
+        assert!(error.message().contains("503"), "{}", error.message());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(client.circuit_status().state, CircuitState::Open);
+    }
+
     #[actix_rt::test]
     async fn check_api_response() {
         // This key will not work, but we can at least get a
@@ -103,10 +990,10 @@ mod test_api_client {
         let city_coords: (f32, f32) = (1.0, 1.0);
         let temperature_fmt = TemperatureFormat::Metric;
 
-        let client = APIClient::build(dummy_key.to_owned());
+        let client = APIClient::build(vec![dummy_key.to_owned()], APIConfig::default());
 
         let query_result = client
-            .query_current_weather(city_coords.0, city_coords.1, temperature_fmt)
+            .query_current_weather(dummy_key, city_coords.0, city_coords.1, temperature_fmt)
             .await;
 
         assert!(query_result.is_ok());
@@ -121,13 +1008,13 @@ mod test_api_client {
         assert!(api_key.is_ok(), "OpenWeatherMap api key not found in env!");
 
         let api_key = api_key.unwrap();
-        let city_coords: (f32, f32) = (34.940079, 36.321911); // Coords for city_id 2960
+        let city_coords: (f32, f32) = (34.940_08, 36.321_91); // Coords for city_id 2960
         let temperature_fmt = TemperatureFormat::Metric;
 
-        let client = APIClient::build(api_key.to_owned());
+        let client = APIClient::build(vec![api_key.to_owned()], APIConfig::default());
 
         let query_result = client
-            .query_current_weather(city_coords.0, city_coords.1, temperature_fmt)
+            .query_current_weather(&api_key, city_coords.0, city_coords.1, temperature_fmt)
             .await;
 
         assert!(query_result.is_ok());